@@ -0,0 +1,50 @@
+//! Throughput of the engine for a client with a short and a long transaction history.
+//!
+//! Run with `cargo bench --bench account_history`. The rate should stay flat as the history grows.
+
+use std::time::{Duration, Instant};
+
+use rust_decimal_macros::dec;
+use transaction_engine::{
+    engine::Engine,
+    types::{Transaction, TransactionIdentifier, TransactionType},
+};
+
+const HISTORY_LENGTHS: [u32; 4] = [0, 1_000, 10_000, 100_000];
+const MEASURED_TRANSACTIONS: u32 = 50_000;
+
+fn deposit(transaction_id: TransactionIdentifier) -> Transaction {
+    Transaction {
+        client_id: 1,
+        transaction_id,
+        transaction_type: TransactionType::DEPOSIT,
+        transaction_amount: Some(dec!(1.5)),
+        reason: None,
+        reason_code: None,
+    }
+}
+
+fn main() {
+    for history_length in HISTORY_LENGTHS {
+        let mut engine = Engine::new();
+        for transaction_id in 0..history_length {
+            engine
+                .process_transaction(&deposit(transaction_id))
+                .unwrap();
+        }
+
+        let start = Instant::now();
+        for transaction_id in history_length..history_length + MEASURED_TRANSACTIONS {
+            engine
+                .process_transaction(&deposit(transaction_id))
+                .unwrap();
+        }
+        let elapsed: Duration = start.elapsed();
+
+        println!(
+            "history of {:>7} transactions: {:>12.0} transactions/s",
+            history_length,
+            MEASURED_TRANSACTIONS as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
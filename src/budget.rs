@@ -0,0 +1,150 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::types::errors::ApplicationError;
+
+/// Limits on the errors accepted in a batch before the batch is rejected as a whole.
+///
+/// Errors are input records which cannot be read as a transaction and rejected transactions.
+/// Ignored transactions are not errors.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ErrorBudget {
+    /// Maximum number of errors, checked as errors occur
+    pub max_errors: Option<u64>,
+    /// Maximum fraction of input records which are errors, between 0 and 1. Checked once the
+    /// input is exhausted, as the rate of a partial batch is not representative.
+    pub max_error_rate: Option<f64>,
+}
+
+impl ErrorBudget {
+    /// Whether any limit is set
+    pub fn is_limited(&self) -> bool {
+        self.max_errors.is_some() || self.max_error_rate.is_some()
+    }
+}
+
+/// Running count of the records and errors of a batch, checked against its error budget. Records
+/// and errors can be counted from multiple threads.
+#[derive(Debug, Default)]
+pub(crate) struct ErrorTally {
+    budget: ErrorBudget,
+    records: AtomicU64,
+    errors_by_category: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl ErrorTally {
+    pub(crate) fn new(budget: ErrorBudget) -> Self {
+        ErrorTally {
+            budget,
+            ..ErrorTally::default()
+        }
+    }
+
+    /// Count an input record, whether or not it can be read as a transaction
+    pub(crate) fn count_record(&self) {
+        self.records.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an error, failing once the maximum number of errors is exceeded
+    pub(crate) fn count_error(&self, err: &ApplicationError) -> Result<(), ApplicationError> {
+        let errors: u64 = {
+            let mut errors_by_category = self.lock_errors();
+            *errors_by_category.entry(err.category()).or_default() += 1;
+            errors_by_category.values().sum()
+        };
+
+        match self.budget.max_errors {
+            Some(max_errors) if errors > max_errors => {
+                Err(self.exceeded_error(format!("the maximum of {} errors", max_errors)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the error rate of the whole batch
+    pub(crate) fn check_error_rate(&self) -> Result<(), ApplicationError> {
+        let records: u64 = self.records.load(Ordering::Relaxed);
+        let errors: u64 = self.lock_errors().values().sum();
+
+        match self.budget.max_error_rate {
+            Some(max_error_rate)
+                if records > 0 && errors as f64 / records as f64 > max_error_rate =>
+            {
+                Err(self.exceeded_error(format!("the maximum error rate of {}", max_error_rate)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn exceeded_error(&self, limit_description: String) -> ApplicationError {
+        let errors_by_category = self.lock_errors();
+        let category_summary: Vec<String> = errors_by_category
+            .iter()
+            .map(|(category, count)| format!("{} {}", category, count))
+            .collect();
+
+        ApplicationError::ErrorBudgetExceeded(format!(
+            "Batch rejected - {} errors in {} records exceeds {} - Errors by category: {}",
+            errors_by_category.values().sum::<u64>(),
+            self.records.load(Ordering::Relaxed),
+            limit_description,
+            category_summary.join(", ")
+        ))
+    }
+
+    // Counts stay consistent even if a thread panicked while holding the lock
+    fn lock_errors(&self) -> std::sync::MutexGuard<'_, BTreeMap<&'static str, u64>> {
+        self.errors_by_category
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        budget::{ErrorBudget, ErrorTally},
+        types::errors::ApplicationError,
+    };
+
+    #[test]
+    fn test_error_budget_limits() {
+        let tally = ErrorTally::new(ErrorBudget {
+            max_errors: Some(2),
+            max_error_rate: None,
+        });
+        for _ in 0..4 {
+            tally.count_record();
+        }
+        let insufficient_funds = ApplicationError::InsufficientFunds("funds".to_string());
+        assert!(tally.count_error(&insufficient_funds).is_ok());
+        assert!(tally
+            .count_error(&ApplicationError::DuplicateTransaction("dup".to_string()))
+            .is_ok());
+        assert!(matches!(
+            tally.count_error(&insufficient_funds),
+            Err(ApplicationError::ErrorBudgetExceeded(message)) if message ==
+                "Batch rejected - 3 errors in 4 records exceeds the maximum of 2 errors - Errors by category: DuplicateTransaction 1, InsufficientFunds 2"
+        ));
+
+        let tally = ErrorTally::new(ErrorBudget {
+            max_errors: None,
+            max_error_rate: Some(0.25),
+        });
+        for _ in 0..4 {
+            tally.count_record();
+        }
+        assert!(tally.count_error(&insufficient_funds).is_ok());
+        assert!(tally.check_error_rate().is_ok());
+        assert!(tally.count_error(&insufficient_funds).is_ok());
+        assert!(matches!(
+            tally.check_error_rate(),
+            Err(ApplicationError::ErrorBudgetExceeded(_))
+        ));
+    }
+}
@@ -1,239 +1,1125 @@
-use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+};
 
-use csv::{ReaderBuilder, Writer};
-use log::{debug, error, warn};
-use rust_decimal_macros::dec;
+use csv::Writer;
+use log::{debug, error, info, warn};
 
-use crate::types::{errors::ApplicationError, Account, AccountView, ClientIdentifier, Transaction};
+use crate::{
+    budget::{ErrorBudget, ErrorTally},
+    input::{
+        read_input_files, resolve_input_files, CsvLayout, InputError, InputFormat, MalformedRecord,
+        STDIN_PATH,
+    },
+    journal::{self, Journal},
+    output::{self, write_file_atomically, OutputFormat, SortKey, SummaryOutput},
+    parallel, snapshot,
+    store::{AccountStore, FileAccountStore, InMemoryAccountStore},
+    types::{
+        errors::ApplicationError, Account, AccountView, ClientIdentifier, Dispute, DisputeState,
+        DisputeView, OperatorAction, Transaction, TransactionIdentifier, TransactionOutcome,
+        TransactionRejection, TransactionType, ValueAmount,
+    },
+};
 
-// const ACCOUNT_DATA_PATH: &str = "data/snapshots/account_data_2024_01_01.csv";
-pub const MB_THRESHOLD: u64 = 2;
-const MAX_DATA_FILE_SIZE_MB: u64 = 1024 * 1024 * MB_THRESHOLD;
+const BYTES_PER_MB: u64 = 1024 * 1024;
 
-pub fn run_transactions(data_file_str: &str) -> Result<(), ApplicationError> {
-    pre_validate_input_file(data_file_str)?;
+/// Original transactions which can be disputed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DisputePolicy {
+    /// Only deposits can be disputed, disputes of withdrawals are rejected
+    #[default]
+    DepositsOnly,
+    /// Deposits and withdrawals can be disputed. Funds move in reverse for a withdrawal, its amount
+    /// is held while disputed and returned to the available funds on a chargeback.
+    DepositsAndWithdrawals,
+}
 
-    // Load transaction requests file
-    let transaction_data: Vec<Transaction> = read_transaction_data(data_file_str)?;
-    debug!("Read transaction data: \n{:?}", transaction_data);
+impl DisputePolicy {
+    /// Whether a transaction of the given type can be disputed
+    pub fn permits(&self, transaction_type: TransactionType) -> bool {
+        match self {
+            DisputePolicy::DepositsOnly => transaction_type == TransactionType::DEPOSIT,
+            DisputePolicy::DepositsAndWithdrawals => transaction_type.is_originating(),
+        }
+    }
+}
 
-    let mut account_data: HashMap<ClientIdentifier, Account> = HashMap::new();
+impl FromStr for DisputePolicy {
+    type Err = ApplicationError;
 
-    let _ = transaction_data
-        .iter()
-        .map(|transaction| process_transaction(&mut account_data, transaction))
-        .collect::<Vec<_>>();
+    fn from_str(policy_str: &str) -> Result<Self, Self::Err> {
+        match policy_str {
+            "deposits" => Ok(DisputePolicy::DepositsOnly),
+            "deposits-and-withdrawals" => Ok(DisputePolicy::DepositsAndWithdrawals),
+            _ => Err(ApplicationError::InvalidData(format!(
+                "Dispute policy [{}] is not one of deposits, deposits-and-withdrawals",
+                policy_str
+            ))),
+        }
+    }
+}
 
-    // Output the results of the transaction
-    debug!("Account data pre-publish: \n{:?}", account_data);
-    publish(account_data.values().collect::<Vec<_>>())?;
+impl fmt::Display for DisputePolicy {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisputePolicy::DepositsOnly => write!(formatter, "deposits"),
+            DisputePolicy::DepositsAndWithdrawals => write!(formatter, "deposits-and-withdrawals"),
+        }
+    }
+}
 
-    Ok(())
+/// Options for processing a batch of transactions
+#[derive(Debug, Default)]
+pub struct BatchConfig {
+    /// Paths of input files in CSV format, or directories of CSV files, processed in order.
+    /// [`STDIN_PATH`] reads from stdin.
+    pub transaction_file_paths: Vec<String>,
+    /// Format of the input files
+    pub input_format: InputFormat,
+    /// Layout of CSV input files
+    pub csv_layout: CsvLayout,
+    /// Destination, format and order of the account summary
+    pub summary_output: SummaryOutput,
+    /// Path of output file for rejected and ignored transactions
+    pub rejections_file_path: Option<String>,
+    /// Path of output file for the operator actions applied, with the operator's reason
+    pub audit_file_path: Option<String>,
+    /// Abort on the first input record which cannot be read as a transaction, instead of skipping
+    /// it
+    pub strict: bool,
+    /// Path of output file for input records which cannot be read as a transaction
+    pub quarantine_file_path: Option<String>,
+    /// Limits on malformed records and rejected transactions, beyond which the batch is rejected
+    /// without an account summary
+    pub error_budget: ErrorBudget,
+    /// Maximum size of each input file in megabytes
+    pub max_file_size_mb: Option<u64>,
+    /// Path of the persistent account store, account state is kept in memory only if not set
+    pub store_path: Option<String>,
+    /// Path of a snapshot file to restore account state from before processing
+    pub load_snapshot_path: Option<String>,
+    /// Path of a snapshot file to save account state to after processing
+    pub save_snapshot_path: Option<String>,
+    /// Path of the journal applied transactions are written to
+    pub journal_path: Option<String>,
+    /// Number of worker threads to process transactions with, sharded by client
+    pub workers: Option<usize>,
+    /// Original transactions which can be disputed
+    pub dispute_policy: DisputePolicy,
+    /// Path of output file for the dispute history of every client
+    pub dispute_history_path: Option<String>,
+}
+
+pub fn run_transactions(config: &BatchConfig) -> Result<(), ApplicationError> {
+    let input_files: Vec<String> =
+        resolve_input_files(&config.transaction_file_paths, config.input_format)?;
+    for input_file in &input_files {
+        pre_validate_input_file(input_file, config.max_file_size_mb)?;
+    }
+
+    // Transactions are persisted as they are applied, a batch rejected by its error budget once
+    // processing has started would leave its applied transactions in the store and journal
+    if config.error_budget.is_limited()
+        && (config.store_path.is_some() || config.journal_path.is_some())
+    {
+        return Err(ApplicationError::InvalidData(
+            "An error budget cannot be combined with a store or journal, transactions are persisted before the batch is checked against the budget".to_string(),
+        ));
+    }
+
+    if let Some(workers) = config.workers.filter(|workers| *workers > 1) {
+        return process_batch_parallel(config, &input_files, workers);
+    }
+
+    match &config.store_path {
+        Some(store_path) => process_batch(
+            config,
+            &input_files,
+            Engine::with_store(FileAccountStore::open(store_path)?)
+                .with_dispute_policy(config.dispute_policy),
+        ),
+        None => process_batch(
+            config,
+            &input_files,
+            Engine::new().with_dispute_policy(config.dispute_policy),
+        ),
+    }
+}
+
+/// Rebuild account state from a transaction journal and output the resulting account summary
+pub fn replay_transactions(
+    journal_file_str: &str,
+    dispute_policy: DisputePolicy,
+    summary_output: &SummaryOutput,
+) -> Result<(), ApplicationError> {
+    let mut engine = Engine::new().with_dispute_policy(dispute_policy);
+    let replayed: u64 = journal::replay(journal_file_str, &mut engine)?;
+    info!(
+        "Replayed [{}] transactions from journal [{}]",
+        replayed, journal_file_str
+    );
+
+    write_summary(&engine, summary_output)
+}
+
+fn process_batch<S: AccountStore>(
+    config: &BatchConfig,
+    input_files: &[String],
+    mut engine: Engine<S>,
+) -> Result<(), ApplicationError> {
+    // Replay starts from empty account state, so a journal has to record every transaction applied
+    // since. It cannot start from account state loaded from a snapshot or held in the store.
+    if let Some(journal_file) = &config.journal_path {
+        if config.load_snapshot_path.is_some() {
+            return Err(ApplicationError::InvalidData(
+                "A journal cannot be combined with loading a snapshot, the journal does not record the account state loaded".to_string(),
+            ));
+        }
+        let journal: Journal = Journal::open(journal_file)?;
+        if journal.is_empty()
+            && (engine.store.accounts().next().is_some()
+                || engine.store.transaction_ids().next().is_some())
+        {
+            return Err(ApplicationError::InvalidData(format!(
+                "Journal [{}] is empty but the account store already holds account state, which the journal does not record",
+                journal_file
+            )));
+        }
+        engine = engine.with_journal(journal);
+    }
+
+    // Opening balances and transaction history carried over from a previous batch
+    if let Some(snapshot_file) = &config.load_snapshot_path {
+        engine.load_snapshot(snapshot_file)?;
+    }
+
+    // Transactions are streamed from the inputs and applied one record at a time
+    let mut batch_writers = BatchWriters::open(config)?;
+    let error_tally = ErrorTally::new(config.error_budget);
+    let mut input_error: Option<ApplicationError> = None;
+
+    for transaction in accept_input(
+        read_input_files(input_files, config.input_format, &config.csv_layout),
+        config.strict,
+        &mut batch_writers.quarantine,
+        &error_tally,
+        &mut input_error,
+    ) {
+        let outcome: TransactionOutcome = engine
+            .process_transaction(&transaction)
+            .unwrap_or_else(|err| TransactionOutcome::Rejected { reason: err });
+        record_outcome(
+            &transaction,
+            &outcome,
+            batch_writers.rejections.as_mut(),
+            batch_writers.audit.as_mut(),
+            &error_tally,
+        )?;
+    }
+    if let Some(err) = input_error {
+        return Err(err);
+    }
+    error_tally.check_error_rate()?;
+
+    finish_batch(config, &engine, batch_writers)
 }
 
-fn read_transaction_data(data_file_str: &str) -> Result<Vec<Transaction>, ApplicationError> {
-    let mut transactions: Vec<Transaction> = Vec::new();
+fn process_batch_parallel(
+    config: &BatchConfig,
+    input_files: &[String],
+    workers: usize,
+) -> Result<(), ApplicationError> {
+    // Workers keep account state in memory, and only for the clients routed to them
+    if config.store_path.is_some()
+        || config.journal_path.is_some()
+        || config.load_snapshot_path.is_some()
+    {
+        return Err(ApplicationError::InvalidData(
+            "Parallel processing only supports in-memory account state, without a store, journal or snapshot to load".to_string(),
+        ));
+    }
 
-    let data_file: File = File::open(data_file_str).map_err(|io_err| {
-        ApplicationError::FileAccess(format!(
-            "Error reading batch data file [{:?}] - [{:?}]",
-            data_file_str, io_err
-        ))
-    })?;
+    let mut batch_writers = BatchWriters::open(config)?;
+    let error_tally = ErrorTally::new(config.error_budget);
+    let mut input_error: Option<ApplicationError> = None;
 
-    let mut reader_builder = ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .has_headers(true)
-        .from_reader(BufReader::new(data_file));
+    let engine: Engine = parallel::process_sharded(
+        accept_input(
+            read_input_files(input_files, config.input_format, &config.csv_layout),
+            config.strict,
+            &mut batch_writers.quarantine,
+            &error_tally,
+            &mut input_error,
+        ),
+        workers,
+        config.dispute_policy,
+        |transaction, outcome| {
+            record_outcome(
+                transaction,
+                outcome,
+                batch_writers.rejections.as_mut(),
+                batch_writers.audit.as_mut(),
+                &error_tally,
+            )
+        },
+    )?;
+    if let Some(err) = input_error {
+        return Err(err);
+    }
+    error_tally.check_error_rate()?;
+
+    finish_batch(config, &engine, batch_writers)
+}
+
+// Transactions of the input, leaving out records which cannot be read. In strict mode the input
+// stops at the first malformed record, otherwise malformed records are written to the quarantine
+// file, if requested, and skipped. The input always stops at the first unreadable file, and once
+// the error budget is exceeded. The error which stopped the input is kept in `input_error` for the
+// caller.
+fn accept_input<'a, I>(
+    records: I,
+    strict: bool,
+    quarantine_writer: &'a mut Option<Writer<File>>,
+    error_tally: &'a ErrorTally,
+    input_error: &'a mut Option<ApplicationError>,
+) -> impl Iterator<Item = Transaction> + 'a
+where
+    I: Iterator<Item = Result<Transaction, InputError>> + 'a,
+{
+    records
+        .map_while(move |record| {
+            let stop_error: ApplicationError = match record {
+                Ok(transaction) => {
+                    error_tally.count_record();
+                    return Some(Some(transaction));
+                }
+                Err(InputError::Malformed(malformed_record)) if !strict => {
+                    error_tally.count_record();
+                    match quarantine_record(
+                        malformed_record,
+                        quarantine_writer.as_mut(),
+                        error_tally,
+                    ) {
+                        Ok(()) => return Some(None),
+                        Err(err) => err,
+                    }
+                }
+                Err(InputError::Malformed(malformed_record)) => malformed_record.into(),
+                Err(InputError::Unreadable(err)) => err,
+            };
+            *input_error = Some(stop_error);
+            None
+        })
+        .flatten()
+}
 
-    for csv_result in reader_builder.deserialize() {
-        match csv_result {
-            Ok(transaction) => {
-                debug!("Transaction read: \n{:?}", transaction);
-                transactions.push(transaction);
+fn quarantine_record(
+    malformed_record: MalformedRecord,
+    quarantine_writer: Option<&mut Writer<File>>,
+    error_tally: &ErrorTally,
+) -> Result<(), ApplicationError> {
+    if let Some(writer) = quarantine_writer {
+        writer
+            .serialize(&malformed_record)
+            .map_err(|err| ApplicationError::csv("Error writing quarantine record", err))?;
+    }
+    let record_error = ApplicationError::from(malformed_record);
+    error!("Error processing record, skipping - {}", record_error);
+
+    error_tally.count_error(&record_error)
+}
+
+// Output files written alongside the batch, each only if requested
+struct BatchWriters {
+    // Transactions which are not applied, for auditing
+    rejections: Option<Writer<File>>,
+    // Operator actions which are applied, for auditing
+    audit: Option<Writer<File>>,
+    // Input records which cannot be read as transactions
+    quarantine: Option<Writer<File>>,
+}
+
+impl BatchWriters {
+    fn open(config: &BatchConfig) -> Result<Self, ApplicationError> {
+        Ok(BatchWriters {
+            rejections: open_csv_writer(config.rejections_file_path.as_deref(), "rejections")?,
+            audit: open_csv_writer(config.audit_file_path.as_deref(), "audit")?,
+            quarantine: open_csv_writer(config.quarantine_file_path.as_deref(), "quarantine")?,
+        })
+    }
+
+    fn flush(self) -> Result<(), ApplicationError> {
+        for (writer, description) in [
+            (self.rejections, "rejections"),
+            (self.audit, "audit"),
+            (self.quarantine, "quarantine"),
+        ] {
+            if let Some(mut writer) = writer {
+                writer.flush().map_err(|err| {
+                    ApplicationError::io(format!("Error writing {} file", description), err)
+                })?;
             }
-            Err(err) => error!("Error processing CSV record, skipping - {}", err),
         }
+
+        Ok(())
     }
+}
 
-    Ok(transactions)
+fn open_csv_writer(
+    file_str: Option<&str>,
+    description: &str,
+) -> Result<Option<Writer<File>>, ApplicationError> {
+    match file_str {
+        Some(file_str) => {
+            let file: File = File::create(file_str).map_err(|err| {
+                ApplicationError::io(
+                    format!("Unable to create {} file [{}]", description, file_str),
+                    err,
+                )
+            })?;
+            Ok(Some(Writer::from_writer(file)))
+        }
+        None => Ok(None),
+    }
 }
 
-fn process_transaction(
-    account_data: &mut HashMap<ClientIdentifier, Account>,
-    incoming_transaction: &Transaction,
+fn record_outcome(
+    transaction: &Transaction,
+    outcome: &TransactionOutcome,
+    rejections_writer: Option<&mut Writer<File>>,
+    audit_writer: Option<&mut Writer<File>>,
+    error_tally: &ErrorTally,
 ) -> Result<(), ApplicationError> {
     debug!(
-        "Process transaction: {}",
-        incoming_transaction.transaction_id
+        "Transaction [{}] outcome: {:?}",
+        transaction.transaction_id, outcome
     );
-    let mut account: Account = match account_data.get(&incoming_transaction.client_id) {
-        None => Account {
-            available: dec!(0.0),
-            client_id: incoming_transaction.client_id,
-            held: dec!(0.0),
-            locked: false,
-            settled_transactions_log: HashMap::new(),
-            disputed_transactions_log: HashMap::new(),
-        },
-        Some(account) => account.clone(),
+
+    if let Some(rejection) = TransactionRejection::new(transaction, outcome) {
+        warn!(
+            "[{}] - Transaction {} - {}",
+            rejection.transaction_id, rejection.outcome, rejection.reason
+        );
+        if let Some(writer) = rejections_writer {
+            writer
+                .serialize(&rejection)
+                .map_err(|err| ApplicationError::csv("Error writing rejection record", err))?;
+        }
+    }
+    if let (Some(operator_action), Some(writer)) =
+        (OperatorAction::new(transaction, outcome), audit_writer)
+    {
+        writer
+            .serialize(&operator_action)
+            .map_err(|err| ApplicationError::csv("Error writing audit record", err))?;
+    }
+
+    match outcome {
+        TransactionOutcome::Rejected { reason } => error_tally.count_error(reason),
+        _ => Ok(()),
+    }
+}
+
+fn finish_batch<S: AccountStore>(
+    config: &BatchConfig,
+    engine: &Engine<S>,
+    batch_writers: BatchWriters,
+) -> Result<(), ApplicationError> {
+    batch_writers.flush()?;
+
+    if let Some(snapshot_file) = &config.save_snapshot_path {
+        engine.save_snapshot(snapshot_file)?;
+    }
+
+    if let Some(dispute_history_file) = &config.dispute_history_path {
+        write_file_atomically(dispute_history_file, |writer| {
+            engine.publish_disputes(writer)
+        })?;
+    }
+
+    // Output the results of the transaction
+    write_summary(engine, &config.summary_output)
+}
+
+fn write_summary<S: AccountStore>(
+    engine: &Engine<S>,
+    summary_output: &SummaryOutput,
+) -> Result<(), ApplicationError> {
+    let publish = |writer: &mut dyn Write| {
+        engine.publish(writer, summary_output.format, summary_output.sort_key)
     };
-    debug!("Account data lookup: \n{:?}", account);
+    match &summary_output.path {
+        Some(output_file) => write_file_atomically(output_file, |writer| publish(writer)),
+        None => publish(&mut io::stdout().lock()),
+    }
+}
+
+/// Transaction processing engine, applying transactions to the accounts held in its store.
+///
+/// Transactions are fed into the engine one at a time and the resulting account summary can be
+/// queried per client or published in full.
+#[derive(Debug, Default)]
+pub struct Engine<S: AccountStore = InMemoryAccountStore> {
+    store: S,
+    journal: Option<Journal>,
+    dispute_policy: DisputePolicy,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine::default()
+    }
+}
+
+impl<S: AccountStore> Engine<S> {
+    /// Create an engine backed by the given account store
+    pub fn with_store(store: S) -> Self {
+        Engine {
+            store,
+            journal: None,
+            dispute_policy: DisputePolicy::default(),
+        }
+    }
+
+    /// Write every applied transaction to the journal before it changes account state
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Apply disputes according to the given policy, instead of the default deposits only
+    pub fn with_dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        self.dispute_policy = dispute_policy;
+        self
+    }
+
+    /// Look up the current state of a client account
+    pub fn account(&self, client_id: ClientIdentifier) -> Option<AccountView> {
+        self.store.get(client_id).map(AccountView::from)
+    }
 
-    // TODO Validate against repeated/duplicate transactions by transaction id
-    // TODO Block accounts that are locked
-    // TODO Introduce transaction to unlock accounts
+    /// Dispute history of a client, in the order the disputes were opened
+    pub fn disputes(&self, client_id: ClientIdentifier) -> Vec<&Dispute> {
+        let mut disputes: Vec<&Dispute> = self
+            .store
+            .get(client_id)
+            .map(|account| account.disputes.values().flatten().collect())
+            .unwrap_or_default();
+        disputes.sort_by_key(|dispute| dispute.sequence);
+        disputes
+    }
+
+    /// Take the account store out of the engine
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    /// Restore account state from a snapshot file, see [`snapshot::load_snapshot`]
+    pub fn load_snapshot(&mut self, snapshot_file_str: &str) -> Result<(), ApplicationError> {
+        snapshot::load_snapshot(snapshot_file_str, &mut self.store)
+    }
+
+    /// Save account state to a snapshot file, see [`snapshot::save_snapshot`]
+    pub fn save_snapshot(&self, snapshot_file_str: &str) -> Result<(), ApplicationError> {
+        snapshot::save_snapshot(snapshot_file_str, &self.store)
+    }
+
+    /// Apply a single transaction to the account data, returning the outcome of the transaction.
+    /// An error is returned when the transaction is rejected, leaving account data unchanged.
+    pub fn process_transaction(
+        &mut self,
+        incoming_transaction: &Transaction,
+    ) -> Result<TransactionOutcome, ApplicationError> {
+        debug!(
+            "Process transaction: {}",
+            incoming_transaction.transaction_id
+        );
+        let client_id: ClientIdentifier = incoming_transaction.client_id;
+        let transaction_id: TransactionIdentifier = incoming_transaction.transaction_id;
+        let existing_account: Option<&Account> = self.store.get(client_id);
+        debug!("Account data lookup: \n{:?}", existing_account);
+
+        // Locked accounts are frozen, no further activity is permitted until an operator unlocks
+        // them. Disputes still open can be settled, so funds held by a partial chargeback are
+        // not stuck.
+        let settles_open_dispute = |account: &Account| {
+            incoming_transaction.transaction_type.is_settling()
+                && account
+                    .disputed_transactions_log
+                    .contains_key(&transaction_id)
+        };
+        if !incoming_transaction.transaction_type.is_administrative()
+            && existing_account
+                .is_some_and(|account| account.locked && !settles_open_dispute(account))
+        {
+            return Err(ApplicationError::AccountLocked(format!(
+                "Transaction id [{}] - Account [{}] is locked",
+                transaction_id, client_id
+            )));
+        }
+        let is_new_account: bool = existing_account.is_none();
+
+        // Operator actions apply to existing accounts only, a lock or unlock never opens an
+        // account
+        if incoming_transaction.transaction_type.is_administrative() && is_new_account {
+            return Err(ApplicationError::InvalidData(format!(
+                "Transaction id [{}] - Account [{}] does not exist",
+                transaction_id, client_id
+            )));
+        }
+
+        // Deposits and withdrawals carry globally unique transaction ids, replays are rejected.
+        // Disputes, resolves and chargebacks reference an existing id and are exempt.
+        let is_originating_transaction = incoming_transaction.transaction_type.is_originating();
+        if is_originating_transaction && self.store.contains_transaction_id(transaction_id) {
+            return Err(duplicate_transaction_error(incoming_transaction));
+        }
+
+        // Referenced transactions are looked up across all clients, so a reference to another
+        // client's transaction is told apart from a reference to an unknown transaction
+        if incoming_transaction.transaction_type.is_referencing() {
+            match self.store.transaction_owner(transaction_id) {
+                Some(owner_id) if owner_id != client_id => {
+                    return Err(transaction_ownership_error(incoming_transaction, owner_id))
+                }
+                None if !self.store.contains_transaction_id(transaction_id) => {
+                    return Err(unknown_transaction_error(incoming_transaction))
+                }
+                _ => {}
+            }
+        }
+
+        // The account is updated in place, the state touched by the transaction is kept aside so
+        // the update can be rolled back if the transaction fails part way through.
+        let account: &mut Account = self.store.entry(client_id);
+        let rollback = AccountRollback::capture(account, transaction_id);
+        let journal: Option<&mut Journal> = self.journal.as_mut();
+        let mut journaled: bool = false;
+        let result = apply_transaction(account, incoming_transaction, self.dispute_policy)
+            .and_then(|outcome| {
+                // Write ahead to the journal, account state only persists once the transaction
+                // is recorded
+                if let (TransactionOutcome::Applied, Some(journal)) = (&outcome, journal) {
+                    journal.append(incoming_transaction)?;
+                    journaled = true;
+                }
+                Ok(outcome)
+            })
+            .and_then(|outcome| {
+                self.store
+                    .commit(client_id, transaction_id, is_originating_transaction)?;
+                Ok(outcome)
+            });
+
+        if result.is_err() {
+            // The journal only holds transactions which were committed, so replay never applies
+            // a transaction reported as failed
+            if let (true, Some(journal)) = (journaled, self.journal.as_mut()) {
+                if let Err(err) = journal.remove_last() {
+                    error!(
+                        "Transaction id [{}] - Journal entry of failed transaction not removed - {}",
+                        transaction_id,
+                        err.report()
+                    );
+                }
+            }
+            if is_new_account {
+                self.store.discard(client_id);
+            } else {
+                rollback.restore(self.store.entry(client_id));
+            }
+        }
+
+        result
+    }
+
+    /// Write a summary of all accounts in the given format, ordered by the sort key
+    pub fn publish<W: Write>(
+        &self,
+        writer: W,
+        output_format: OutputFormat,
+        sort_key: SortKey,
+    ) -> Result<(), ApplicationError> {
+        output::write_accounts(
+            writer,
+            output_format,
+            sort_key,
+            self.store.accounts().map(AccountView::from),
+        )
+    }
+
+    /// Write the dispute history of every client in CSV format, see [`Engine::disputes`]
+    pub fn publish_disputes<W: Write>(&self, writer: W) -> Result<(), ApplicationError> {
+        output::write_disputes(
+            writer,
+            self.store.accounts().flat_map(|account| {
+                account
+                    .disputes
+                    .values()
+                    .flatten()
+                    .map(|dispute| DisputeView::new(account.client_id, dispute))
+            }),
+        )
+    }
+}
+
+// Apply a transaction to an account in place. A transaction only changes the balances of the
+// account and the log entries of its own transaction id.
+fn apply_transaction(
+    account: &mut Account,
+    incoming_transaction: &Transaction,
+    dispute_policy: DisputePolicy,
+) -> Result<TransactionOutcome, ApplicationError> {
+    let mut outcome = TransactionOutcome::Applied;
     match incoming_transaction.transaction_type {
-        crate::types::TransactionType::DEPOSIT => {
+        TransactionType::DEPOSIT => {
             match incoming_transaction.transaction_amount {
+                Some(amount) if amount <= ValueAmount::ZERO => return Err(non_positive_amount_error(incoming_transaction.transaction_id, amount)),
                 Some(amount) => account.available += amount,
                 None => return Err(ApplicationError::InvalidData(format!("Transaction id [{}] - Transaction amount value missing for deposit transaction type", incoming_transaction.transaction_id))),
             }
-            account
-                .settled_transactions_log
-                .insert(incoming_transaction.transaction_id, *incoming_transaction);
+            account.settled_transactions_log.insert(
+                incoming_transaction.transaction_id,
+                incoming_transaction.clone(),
+            );
         }
-        crate::types::TransactionType::WITHDRAWAL => {
+        TransactionType::WITHDRAWAL => {
             // Deduct value from account
             match incoming_transaction.transaction_amount {
+                Some(amount) if amount <= ValueAmount::ZERO => return Err(non_positive_amount_error(incoming_transaction.transaction_id, amount)),
                 Some(amount) => {
                     // If available funds are not sufficient, fail the transaction.
-                    if account.available > amount {
-                        account.available -= amount;
+                    if account.available < amount {
+                        return Err(ApplicationError::InsufficientFunds(format!(
+                            "Transaction id [{}] - Insufficient funds, withdrawal of [{}] exceeds available [{}]",
+                            incoming_transaction.transaction_id, amount, account.available
+                        )));
                     }
+                    account.available -= amount;
                 },
                 None => return Err(ApplicationError::InvalidData(format!("Transaction id [{}] - Transaction amount value missing for withdrawal transaction type", incoming_transaction.transaction_id))),
             }
-            account
-                .settled_transactions_log
-                .insert(incoming_transaction.transaction_id, *incoming_transaction);
+            account.settled_transactions_log.insert(
+                incoming_transaction.transaction_id,
+                incoming_transaction.clone(),
+            );
         }
-        crate::types::TransactionType::CHARGEBACK => {
+        TransactionType::CHARGEBACK => {
             // Like a RESOLVE transaction, is a subsequent transaction to a DISPUTE.
             // Locks the account
 
-            // Move amount defined by transaction in question, from held back to available and
-            // allocate the transaction back to the settled log.
-            let dropped_transaction = account
-                .disputed_transactions_log
-                .remove(&incoming_transaction.transaction_id);
-            match dropped_transaction {
-                Some(transaction) => {
-                    match transaction.transaction_amount {
-                        Some(amount) => {
-                            account.held -= amount;
-                        },
-                        None => error!("[{}] - Data corruption error - Dropped transaction missing value amount", transaction.transaction_id),
-                    }
+            // Withdraw the disputed amount of the transaction in question from held funds, or
+            // return it to the available funds for a withdrawal, and freeze the account.
+            check_dispute_transition(account, incoming_transaction, DisputeState::ChargedBack)?;
+            match take_log_amount(&mut account.disputed_transactions_log, incoming_transaction, "disputed")? {
+                Some(portion) => {
+                    let amount: ValueAmount = portion.transaction_amount.unwrap_or_default();
+                    account.available += amount - disputed_available_amount(&portion, amount);
+                    account.held -= amount;
+                    account.locked = true;
+                    record_dispute(account, incoming_transaction, amount, DisputeState::ChargedBack);
                 },
-                None => warn!("[{}] - Resolve transaction received but referenced an unsettled transaction not found for account [{}]", incoming_transaction.transaction_id, account.client_id),
+                None => outcome = TransactionOutcome::Ignored { reason: format!("Chargeback transaction received but referenced an unsettled transaction not found for account [{}]", account.client_id) },
             }
         }
-        crate::types::TransactionType::DISPUTE => {
-            // Move amount defined by transaction in question, from available to held and allocate
-            // the transaction to the unsettled log.
-            let unsettled_transaction = account
+        TransactionType::DISPUTE => {
+            // Hold the disputed amount of the transaction in question and allocate it to the
+            // unsettled log, if the dispute policy permits disputing the transaction. A transaction
+            // can be disputed in several parts, up to its undisputed amount, until its dispute ends.
+            check_dispute_transition(account, incoming_transaction, DisputeState::Open)?;
+            let settled_type: Option<TransactionType> = account
                 .settled_transactions_log
-                .remove(&incoming_transaction.transaction_id);
-            match unsettled_transaction {
-                Some(transaction) => {
-                    match transaction.transaction_amount {
-                        Some(amount) => {
-                            account.disputed_transactions_log.insert(transaction.transaction_id, transaction);
-                            account.available -= amount;
-                            account.held += amount;
-                        },
-                        None => error!("[{}] - Data corruption error - Settled account transaction missing value amount", transaction.transaction_id),
-                    }
+                .get(&incoming_transaction.transaction_id)
+                .map(|transaction| transaction.transaction_type);
+            if let Some(settled_type) =
+                settled_type.filter(|settled_type| !dispute_policy.permits(*settled_type))
+            {
+                return Err(ApplicationError::DisputeNotPermitted(format!(
+                    "Transaction id [{}] - Dispute policy [{}] does not permit disputing a {} transaction",
+                    incoming_transaction.transaction_id, dispute_policy, format!("{:?}", settled_type).to_lowercase()
+                )));
+            }
+            match take_log_amount(&mut account.settled_transactions_log, incoming_transaction, "undisputed")? {
+                Some(portion) => {
+                    let amount: ValueAmount = portion.transaction_amount.unwrap_or_default();
+                    account.available -= disputed_available_amount(&portion, amount);
+                    account.held += amount;
+                    add_log_amount(&mut account.disputed_transactions_log, portion);
+                    record_dispute(account, incoming_transaction, amount, DisputeState::Open);
                 },
-                None => warn!("[{}] - Dispute transaction received but referenced transaction not found for account [{}]", incoming_transaction.transaction_id, account.client_id),
+                None => outcome = TransactionOutcome::Ignored { reason: format!("Dispute transaction received but referenced transaction not found for account [{}]", account.client_id) },
             }
         }
-        crate::types::TransactionType::RESOLVE => {
-            // Move amount defined by transaction in question, from held back to available and
-            // allocate the transaction back to the settled log.
-            let resettled_transaction = account
-                .disputed_transactions_log
-                .remove(&incoming_transaction.transaction_id);
-            match resettled_transaction {
-                Some(transaction) => {
-                    match transaction.transaction_amount {
-                        Some(amount) => {
-                            account.settled_transactions_log.insert(transaction.transaction_id, transaction);
-                            account.available += amount;
-                            account.held -= amount;
-                        },
-                        None => error!("[{}] - Data corruption error - Unsettled account transaction missing value amount", transaction.transaction_id),
-                    }
+        TransactionType::RESOLVE => {
+            // Release the disputed amount of the transaction in question from held funds, undoing
+            // the dispute, and allocate it back to the settled log.
+            check_dispute_transition(account, incoming_transaction, DisputeState::Resolved)?;
+            match take_log_amount(&mut account.disputed_transactions_log, incoming_transaction, "disputed")? {
+                Some(portion) => {
+                    let amount: ValueAmount = portion.transaction_amount.unwrap_or_default();
+                    account.available += disputed_available_amount(&portion, amount);
+                    account.held -= amount;
+                    add_log_amount(&mut account.settled_transactions_log, portion);
+                    record_dispute(account, incoming_transaction, amount, DisputeState::Resolved);
                 },
-                None => warn!("[{}] - Resolve transaction received but referenced an unsettled transaction not found for account [{}]", incoming_transaction.transaction_id, account.client_id),
+                None => outcome = TransactionOutcome::Ignored { reason: format!("Resolve transaction received but referenced an unsettled transaction not found for account [{}]", account.client_id) },
+            }
+        }
+        TransactionType::EXPIRE => {
+            // Release the whole remaining disputed amount of the transaction in question as for a
+            // resolve, the dispute lapsed without being resolved or charged back in time.
+            check_dispute_transition(account, incoming_transaction, DisputeState::Expired)?;
+            if let Some(amount) = incoming_transaction.transaction_amount {
+                return Err(ApplicationError::InvalidData(format!(
+                    "Transaction id [{}] - Amount [{}] not accepted, an expiry releases the whole disputed amount",
+                    incoming_transaction.transaction_id, amount
+                )));
+            }
+            match take_log_amount(&mut account.disputed_transactions_log, incoming_transaction, "disputed")? {
+                Some(portion) => {
+                    let amount: ValueAmount = portion.transaction_amount.unwrap_or_default();
+                    account.available += disputed_available_amount(&portion, amount);
+                    account.held -= amount;
+                    add_log_amount(&mut account.settled_transactions_log, portion);
+                    record_dispute(account, incoming_transaction, amount, DisputeState::Expired);
+                },
+                None => outcome = TransactionOutcome::Ignored { reason: format!("Expire transaction received but referenced an unsettled transaction not found for account [{}]", account.client_id) },
+            }
+        }
+        TransactionType::LOCK | TransactionType::UNLOCK => {
+            // Operator actions freezing or reinstating the account, the reason is kept with the
+            // transaction in the journal and the audit output as the record of the action.
+            let (lock, action) = match incoming_transaction.transaction_type {
+                TransactionType::LOCK => (true, "lock"),
+                _ => (false, "unlock"),
+            };
+            let reason: &str = match incoming_transaction.reason.as_deref().map(str::trim) {
+                Some(reason) if !reason.is_empty() => reason,
+                _ => {
+                    return Err(ApplicationError::InvalidData(format!(
+                        "Transaction id [{}] - Operator reason missing for {} transaction type",
+                        incoming_transaction.transaction_id, action
+                    )))
+                }
+            };
+            if account.locked == lock {
+                outcome = TransactionOutcome::Ignored {
+                    reason: format!(
+                        "{} transaction received but account [{}] is already {}ed",
+                        action, account.client_id, action
+                    ),
+                };
+            } else {
+                info!(
+                    "Transaction id [{}] - Account [{}] {}ed - {}",
+                    incoming_transaction.transaction_id, account.client_id, action, reason
+                );
+                account.locked = lock;
             }
         }
     }
 
-    account_data.insert(account.client_id, account);
+    Ok(outcome)
+}
 
-    Ok(())
+// Check the last dispute of the referenced transaction, if it has one, may move to the given state.
+// Once it has ended, a dispute of the undisputed remainder opens a new dispute instead. Transactions
+// without a dispute are left to the transaction logs.
+fn check_dispute_transition(
+    account: &Account,
+    incoming_transaction: &Transaction,
+    next_state: DisputeState,
+) -> Result<(), ApplicationError> {
+    let transaction_id: TransactionIdentifier = incoming_transaction.transaction_id;
+    let disputes_remainder: bool = next_state == DisputeState::Open
+        && account
+            .settled_transactions_log
+            .contains_key(&transaction_id);
+    match account
+        .disputes
+        .get(&transaction_id)
+        .and_then(|disputes| disputes.last())
+    {
+        Some(dispute) if !dispute.state.can_transition_to(next_state) && !disputes_remainder => {
+            Err(ApplicationError::DisputeTransition(format!(
+                "Transaction id [{}] - Dispute has ended as [{:?}], {} transaction not permitted",
+                incoming_transaction.transaction_id,
+                dispute.state,
+                format!("{:?}", incoming_transaction.transaction_type).to_lowercase()
+            )))
+        }
+        _ => Ok(()),
+    }
 }
 
-fn publish(account_data: Vec<&Account>) -> Result<(), ApplicationError> {
-    debug!("*****************************");
-    debug!("Account data collection: \n{:?}", account_data);
-    let mut csv_writer = Writer::from_writer(vec![]);
-    let _ser_result = account_data
-        .iter()
-        .map(|account| {
-            debug!("Account preserialized: \n{:?}", account);
-            let account_view: AccountView = AccountView::from((**account).clone());
-            debug!("Serialized Account View: \n{:?}", account_view);
-            let _ = csv_writer.serialize(&account_view).map_err(|err| {
-                error!(
-                    "Error serializing account status [{:?}] - [{:?}]",
-                    account_view.client_id.clone(),
-                    err
-                );
-            });
+// Record an amount disputed, resolved, charged back or expired on the dispute of the referenced
+// transaction, once the transaction logs are updated. A dispute of a transaction without an open
+// dispute opens a new one, which ends once none of its amount remains disputed, see
+// `Dispute::ended_state`.
+fn record_dispute(
+    account: &mut Account,
+    incoming_transaction: &Transaction,
+    amount: ValueAmount,
+    next_state: DisputeState,
+) {
+    let transaction_id: TransactionIdentifier = incoming_transaction.transaction_id;
+    let disputed_amount: ValueAmount = account
+        .disputed_transactions_log
+        .get(&transaction_id)
+        .and_then(|transaction| transaction.transaction_amount)
+        .unwrap_or_default();
+    let disputes: &mut Vec<Dispute> = account.disputes.entry(transaction_id).or_default();
+    if disputes
+        .last()
+        .is_none_or(|dispute| dispute.state != DisputeState::Open)
+    {
+        account.dispute_count += 1;
+        disputes.push(Dispute {
+            transaction_id,
+            sequence: account.dispute_count,
+            state: DisputeState::Open,
+            reason_code: None,
+            // Transactions disputed before disputes were recorded start from the amount which was
+            // disputed before this transaction
+            amount: match next_state {
+                DisputeState::Open => disputed_amount - amount,
+                _ => disputed_amount + amount,
+            },
+            resolved: ValueAmount::ZERO,
+            charged_back: ValueAmount::ZERO,
+        });
+    }
+    let Some(dispute) = disputes.last_mut() else {
+        return;
+    };
+
+    match next_state {
+        DisputeState::Open => {
+            dispute.amount += amount;
+            if dispute.reason_code.is_none() {
+                dispute.reason_code = incoming_transaction.reason_code.clone();
+            }
+        }
+        DisputeState::Resolved => dispute.resolved += amount,
+        DisputeState::ChargedBack => dispute.charged_back += amount,
+        DisputeState::Expired => {}
+    }
+    if disputed_amount.is_zero() {
+        dispute.state = dispute.ended_state();
+    }
+}
+
+// Take the amount a dispute, resolve or chargeback acts on out of the log entry of the referenced
+// transaction, returning it as a transaction of the same type. The amount carried by the incoming
+// transaction is taken if it has one, otherwise the full amount of the entry, and the entry is
+// removed once none of its amount remains. `None` if the log has no entry for the transaction.
+fn take_log_amount(
+    log: &mut HashMap<TransactionIdentifier, Transaction>,
+    incoming_transaction: &Transaction,
+    log_description: &str,
+) -> Result<Option<Transaction>, ApplicationError> {
+    let transaction_id: TransactionIdentifier = incoming_transaction.transaction_id;
+    let Some(entry) = log.get_mut(&transaction_id) else {
+        return Ok(None);
+    };
+    let Some(entry_amount) = entry.transaction_amount else {
+        error!(
+            "[{}] - Data corruption error - Logged transaction missing value amount",
+            transaction_id
+        );
+        return Ok(None);
+    };
+
+    let amount: ValueAmount = match incoming_transaction.transaction_amount {
+        None => entry_amount,
+        Some(amount) if amount <= ValueAmount::ZERO => {
+            return Err(non_positive_amount_error(transaction_id, amount))
+        }
+        Some(amount) if amount > entry_amount => {
+            return Err(ApplicationError::InvalidData(format!(
+                "Transaction id [{}] - Amount [{}] exceeds the {} amount [{}]",
+                transaction_id, amount, log_description, entry_amount
+            )))
+        }
+        Some(amount) => amount,
+    };
+    let portion = Transaction {
+        transaction_amount: Some(amount),
+        ..entry.clone()
+    };
+    match amount == entry_amount {
+        true => {
+            log.remove(&transaction_id);
+        }
+        false => entry.transaction_amount = Some(entry_amount - amount),
+    }
+
+    Ok(Some(portion))
+}
+
+// Add a portion of a transaction to the log, onto the amount of any entry for the same transaction
+fn add_log_amount(log: &mut HashMap<TransactionIdentifier, Transaction>, portion: Transaction) {
+    let amount: ValueAmount = portion.transaction_amount.unwrap_or_default();
+    log.entry(portion.transaction_id)
+        .and_modify(|entry| {
+            entry.transaction_amount = Some(entry.transaction_amount.unwrap_or_default() + amount)
         })
-        .collect::<Vec<_>>();
+        .or_insert(portion);
+}
 
-    let csv_bytes = csv_writer.into_inner().map_err(|err| {
-        ApplicationError::CSV(format!("Error serializing CSV data - [{:?}]", err))
-    })?;
-    let csv_data: String = String::from_utf8(csv_bytes).map_err(|err| {
-        ApplicationError::CSV(format!("Error serializing CSV data - [{:?}]", err))
-    })?;
+// Amount moved out of the available funds when a transaction is disputed. A disputed deposit moves
+// its amount from available to held funds. The amount of a withdrawal was already deducted from the
+// available funds, so a disputed withdrawal holds its amount on top of them, and a chargeback
+// returns it to the available funds.
+fn disputed_available_amount(transaction: &Transaction, amount: ValueAmount) -> ValueAmount {
+    match transaction.transaction_type {
+        TransactionType::WITHDRAWAL => ValueAmount::ZERO,
+        _ => amount,
+    }
+}
 
-    println!("{}", csv_data);
+// Account state touched by a single transaction, captured before the transaction is applied
+struct AccountRollback {
+    transaction_id: TransactionIdentifier,
+    available: ValueAmount,
+    held: ValueAmount,
+    locked: bool,
+    settled: Option<Transaction>,
+    disputed: Option<Transaction>,
+    disputes: Option<Vec<Dispute>>,
+    dispute_count: u64,
+}
 
-    Ok(())
+impl AccountRollback {
+    fn capture(account: &Account, transaction_id: TransactionIdentifier) -> Self {
+        AccountRollback {
+            transaction_id,
+            available: account.available,
+            held: account.held,
+            locked: account.locked,
+            settled: account
+                .settled_transactions_log
+                .get(&transaction_id)
+                .cloned(),
+            disputed: account
+                .disputed_transactions_log
+                .get(&transaction_id)
+                .cloned(),
+            disputes: account.disputes.get(&transaction_id).cloned(),
+            dispute_count: account.dispute_count,
+        }
+    }
+
+    // Return the account to the state it was captured in
+    fn restore(self, account: &mut Account) {
+        account.available = self.available;
+        account.held = self.held;
+        account.locked = self.locked;
+        account.dispute_count = self.dispute_count;
+        restore_log_entry(
+            &mut account.settled_transactions_log,
+            self.transaction_id,
+            self.settled,
+        );
+        restore_log_entry(
+            &mut account.disputed_transactions_log,
+            self.transaction_id,
+            self.disputed,
+        );
+        restore_log_entry(&mut account.disputes, self.transaction_id, self.disputes);
+    }
+}
+
+fn restore_log_entry<T>(
+    log: &mut HashMap<TransactionIdentifier, T>,
+    transaction_id: TransactionIdentifier,
+    entry: Option<T>,
+) {
+    match entry {
+        Some(entry) => log.insert(transaction_id, entry),
+        None => log.remove(&transaction_id),
+    };
+}
+
+// Amounts of every transaction type are positive, the transaction type gives the direction
+fn non_positive_amount_error(
+    transaction_id: TransactionIdentifier,
+    amount: ValueAmount,
+) -> ApplicationError {
+    ApplicationError::InvalidData(format!(
+        "Transaction id [{}] - Amount [{}] must be greater than zero",
+        transaction_id, amount
+    ))
+}
+
+pub(crate) fn duplicate_transaction_error(transaction: &Transaction) -> ApplicationError {
+    ApplicationError::DuplicateTransaction(format!(
+        "Transaction id [{}] - Duplicate transaction id for client [{}]",
+        transaction.transaction_id, transaction.client_id
+    ))
+}
+
+pub(crate) fn unknown_transaction_error(transaction: &Transaction) -> ApplicationError {
+    ApplicationError::UnknownTransaction(format!(
+        "Transaction id [{}] - Referenced transaction is unknown for client [{}]",
+        transaction.transaction_id, transaction.client_id
+    ))
+}
+
+pub(crate) fn transaction_ownership_error(
+    transaction: &Transaction,
+    owner_id: ClientIdentifier,
+) -> ApplicationError {
+    ApplicationError::TransactionOwnership(format!(
+        "Transaction id [{}] - Referenced transaction belongs to client [{}], not client [{}]",
+        transaction.transaction_id, owner_id, transaction.client_id
+    ))
 }
 
 /// Validate application argument/s
 /// - Data file is accessible
-/// - File size is under the maximum supported batch size
-fn pre_validate_input_file(data_file_str: &str) -> Result<(), ApplicationError> {
+/// - File size is under the configured maximum batch size, if any
+fn pre_validate_input_file(
+    data_file_str: &str,
+    max_file_size_mb: Option<u64>,
+) -> Result<(), ApplicationError> {
+    // Stdin is streamed and has no size to check up front
+    if data_file_str == STDIN_PATH {
+        return Ok(());
+    }
     let file_path: &Path = Path::new(data_file_str);
 
     // Test accessibility
-    let transaction_file = match File::open(file_path) {
-        Err(err) => return Err(ApplicationError::FileAccess(format!("Unable to open [{:?}] - {}", file_path, err))),
-        Ok(file) => file,
+    let transaction_file: File = File::open(file_path)
+        .map_err(|err| ApplicationError::io(format!("Unable to open [{:?}]", file_path), err))?;
+
+    // Input is streamed, so file size is only limited when explicitly configured
+    let Some(max_file_size_mb) = max_file_size_mb else {
+        return Ok(());
     };
 
-    // Check the file size is under the supported maximum
+    // Check the file size is under the configured maximum
     match transaction_file.metadata() {
-        Err(err) => panic!(
-            "Unable to read file metadata for file [{}].\n{}",
-            data_file_str, err,
-        ),
+        Err(err) => {
+            return Err(ApplicationError::io(
+                format!("Unable to read file metadata for file [{}]", data_file_str),
+                err,
+            ))
+        }
         Ok(metadata) => {
             // Only process transaction files smaller than the maximum threshold.
-            if metadata.len() > MAX_DATA_FILE_SIZE_MB {
+            if metadata.len() > max_file_size_mb.saturating_mul(BYTES_PER_MB) {
                 return Err(ApplicationError::InvalidData(format!(
                     "Data file [{}] size of [{}] bytes which exceeds input limit of {} megabytes",
                     data_file_str,
                     metadata.len(),
-                    MB_THRESHOLD,
+                    max_file_size_mb,
                 )));
             }
         }
@@ -244,156 +1130,1140 @@ fn pre_validate_input_file(data_file_str: &str) -> Result<(), ApplicationError>
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::{env, fs, io};
 
     use rust_decimal_macros::dec;
 
     use crate::{
-        engine::process_transaction,
-        types::{Account, ClientIdentifier, Transaction, TransactionType},
+        engine::{DisputePolicy, Engine},
+        journal::{self, Journal},
+        output::{OutputFormat, SortKey},
+        store::{AccountStore, InMemoryAccountStore},
+        types::{
+            errors::ApplicationError, Account, ClientIdentifier, DisputeState, Transaction,
+            TransactionIdentifier, TransactionOutcome, TransactionType,
+        },
     };
 
+    // In memory store which fails to commit while `fail_commits` is set
+    #[derive(Default)]
+    struct FailingStore {
+        accounts: InMemoryAccountStore,
+        fail_commits: bool,
+    }
+
+    impl AccountStore for FailingStore {
+        fn get(&self, client_id: ClientIdentifier) -> Option<&Account> {
+            self.accounts.get(client_id)
+        }
+
+        fn entry(&mut self, client_id: ClientIdentifier) -> &mut Account {
+            self.accounts.entry(client_id)
+        }
+
+        fn commit(
+            &mut self,
+            client_id: ClientIdentifier,
+            transaction_id: TransactionIdentifier,
+            processed: bool,
+        ) -> Result<(), ApplicationError> {
+            if self.fail_commits {
+                return Err(ApplicationError::io(
+                    "Commit failed",
+                    io::Error::other("device full"),
+                ));
+            }
+            self.accounts.commit(client_id, transaction_id, processed)
+        }
+
+        fn discard(&mut self, client_id: ClientIdentifier) {
+            self.accounts.discard(client_id);
+        }
+
+        fn put(
+            &mut self,
+            account: Account,
+            processed_transaction_id: Option<TransactionIdentifier>,
+        ) -> Result<(), ApplicationError> {
+            self.accounts.put(account, processed_transaction_id)
+        }
+
+        fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+            self.accounts.accounts()
+        }
+
+        fn contains_transaction_id(&self, transaction_id: TransactionIdentifier) -> bool {
+            self.accounts.contains_transaction_id(transaction_id)
+        }
+
+        fn transaction_owner(
+            &self,
+            transaction_id: TransactionIdentifier,
+        ) -> Option<ClientIdentifier> {
+            self.accounts.transaction_owner(transaction_id)
+        }
+
+        fn transaction_ids(&self) -> Box<dyn Iterator<Item = TransactionIdentifier> + '_> {
+            self.accounts.transaction_ids()
+        }
+
+        fn insert_transaction_ids(
+            &mut self,
+            transaction_ids: &[TransactionIdentifier],
+        ) -> Result<(), ApplicationError> {
+            self.accounts.insert_transaction_ids(transaction_ids)
+        }
+    }
+
     #[test]
     fn test_deposit_withdrawal_transaction_success() {
-        let account_data: &mut HashMap<ClientIdentifier, Account> = &mut HashMap::new();
-        let transaction_result = process_transaction(
-            account_data,
-            &Transaction {
-                client_id: 1,
-                transaction_id: 1,
-                transaction_type: TransactionType::DEPOSIT,
-                transaction_amount: Some(dec!(100.0)),
-            },
-        );
+        let mut engine = Engine::new();
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(100.0)),
+            reason: None,
+            reason_code: None,
+        });
         assert!(transaction_result.is_ok());
-        assert_eq!(account_data.len(), 1);
-        assert_eq!(account_data.get(&1).unwrap().available, dec!(100.0));
+        assert_eq!(engine.store.accounts().count(), 1);
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(100.0));
 
-        let transaction_result = process_transaction(
-            account_data,
-            &Transaction {
-                client_id: 1,
-                transaction_id: 2,
-                transaction_type: TransactionType::WITHDRAWAL,
-                transaction_amount: Some(dec!(55.0)),
-            },
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            transaction_type: TransactionType::WITHDRAWAL,
+            transaction_amount: Some(dec!(55.0)),
+            reason: None,
+            reason_code: None,
+        });
+        assert!(transaction_result.is_ok());
+        assert_eq!(engine.store.accounts().count(), 1);
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(45.0));
+        assert!(!engine.store.get(1).unwrap().locked);
+        assert_eq!(engine.store.get(1).unwrap().client_id, 1);
+    }
+
+    #[test]
+    fn test_account_lookup_and_publish() {
+        let mut engine = Engine::new();
+        assert!(engine.account(1).is_none());
+
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(12.5)),
+            reason: None,
+            reason_code: None,
+        });
+        assert!(transaction_result.is_ok());
+
+        let account_view = engine.account(1).unwrap();
+        assert_eq!(account_view.available, dec!(12.5));
+        assert_eq!(account_view.total, dec!(12.5));
+
+        let mut output: Vec<u8> = Vec::new();
+        assert!(engine
+            .publish(&mut output, OutputFormat::Csv, SortKey::Client)
+            .is_ok());
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,locked,total\n1,12.5000,0.0000,false,12.5000\n"
         );
+    }
+
+    #[test]
+    fn test_withdrawal_insufficient_funds() {
+        let mut engine = Engine::new();
+        let _ = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(50.0)),
+            reason: None,
+            reason_code: None,
+        });
+
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            transaction_type: TransactionType::WITHDRAWAL,
+            transaction_amount: Some(dec!(50.01)),
+            reason: None,
+            reason_code: None,
+        });
+        assert!(matches!(
+            transaction_result,
+            Err(ApplicationError::InsufficientFunds(_))
+        ));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(50.0));
+        assert!(!engine
+            .store
+            .get(1)
+            .unwrap()
+            .settled_transactions_log
+            .contains_key(&2));
+
+        // A refused withdrawal cannot be disputed
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            transaction_type: TransactionType::DISPUTE,
+            transaction_amount: None,
+            reason: None,
+            reason_code: None,
+        });
+        assert!(matches!(
+            transaction_result,
+            Err(ApplicationError::UnknownTransaction(_))
+        ));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(0.0));
+
+        // Negative and zero amounts cannot get around the available funds
+        for (transaction_type, amount) in [
+            (TransactionType::WITHDRAWAL, dec!(-50.0)),
+            (TransactionType::DEPOSIT, dec!(-10.0)),
+            (TransactionType::DEPOSIT, dec!(0.0)),
+        ] {
+            let transaction_result = engine.process_transaction(&Transaction {
+                client_id: 1,
+                transaction_id: 4,
+                transaction_type,
+                transaction_amount: Some(amount),
+                reason: None,
+                reason_code: None,
+            });
+            assert!(matches!(
+                transaction_result,
+                Err(ApplicationError::InvalidData(_))
+            ));
+        }
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(50.0));
+        assert!(!engine.store.contains_transaction_id(4));
+
+        // Withdrawing the full available balance succeeds
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 3,
+            transaction_type: TransactionType::WITHDRAWAL,
+            transaction_amount: Some(dec!(50.0)),
+            reason: None,
+            reason_code: None,
+        });
         assert!(transaction_result.is_ok());
-        assert_eq!(account_data.len(), 1);
-        assert_eq!(account_data.get(&1).unwrap().available, dec!(45.0));
-        assert!(!account_data.get(&1).unwrap().locked);
-        assert_eq!(account_data.get(&1).unwrap().client_id, 1);
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(0.0));
     }
 
     #[test]
     fn test_dispute_chargeback_transaction_success() {
-        let account_data: &mut HashMap<ClientIdentifier, Account> = &mut HashMap::new();
-        let transaction_result = process_transaction(
-            account_data,
-            &Transaction {
-                client_id: 1,
-                transaction_id: 1,
-                transaction_type: TransactionType::DEPOSIT,
-                transaction_amount: Some(dec!(100.0)),
-            },
-        );
+        let mut engine = Engine::new();
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(100.0)),
+            reason: None,
+            reason_code: None,
+        });
         assert!(transaction_result.is_ok());
-        assert_eq!(account_data.get(&1).unwrap().available, dec!(100.0));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(100.0));
 
-        let transaction_result = process_transaction(
-            account_data,
-            &Transaction {
-                client_id: 1,
-                transaction_id: 2,
-                transaction_type: TransactionType::DEPOSIT,
-                transaction_amount: Some(dec!(41.7)),
-            },
-        );
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(41.7)),
+            reason: None,
+            reason_code: None,
+        });
         assert!(transaction_result.is_ok());
-        assert_eq!(account_data.get(&1).unwrap().available, dec!(141.7));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(141.7));
 
         // Dispute transaction 2
-        let transaction_result = process_transaction(
-            account_data,
-            &Transaction {
-                client_id: 1,
-                transaction_id: 2,
-                transaction_type: TransactionType::DISPUTE,
-                transaction_amount: None,
-            },
-        );
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            transaction_type: TransactionType::DISPUTE,
+            transaction_amount: None,
+            reason: None,
+            reason_code: None,
+        });
         assert!(transaction_result.is_ok());
-        assert_eq!(account_data.get(&1).unwrap().held, dec!(41.7));
-        assert_eq!(account_data.get(&1).unwrap().available, dec!(100.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(41.7));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(100.0));
 
         // Resolve the dispute
-        let transaction_result = process_transaction(
-            account_data,
-            &Transaction {
-                client_id: 1,
-                transaction_id: 2,
-                transaction_type: TransactionType::CHARGEBACK,
-                transaction_amount: None,
-            },
-        );
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            transaction_type: TransactionType::CHARGEBACK,
+            transaction_amount: None,
+            reason: None,
+            reason_code: None,
+        });
         assert!(transaction_result.is_ok());
-        assert_eq!(account_data.get(&1).unwrap().held, dec!(0.0));
-        assert_eq!(account_data.get(&1).unwrap().available, dec!(100.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(0.0));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(100.0));
+        assert!(engine.store.get(1).unwrap().locked);
     }
 
     #[test]
-    fn test_dispute_resolve_transaction_success() {
-        let account_data: &mut HashMap<ClientIdentifier, Account> = &mut HashMap::new();
-        let transaction_result = process_transaction(
-            account_data,
-            &Transaction {
+    fn test_locked_account_rejects_transactions() {
+        let mut engine = Engine::new();
+        let _ = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(100.0)),
+            reason: None,
+            reason_code: None,
+        });
+        let _ = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(20.0)),
+            reason: None,
+            reason_code: None,
+        });
+        let _ = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DISPUTE,
+            transaction_amount: None,
+            reason: None,
+            reason_code: None,
+        });
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::CHARGEBACK,
+            transaction_amount: None,
+            reason: None,
+            reason_code: None,
+        });
+        assert!(transaction_result.is_ok());
+        assert!(engine.store.get(1).unwrap().locked);
+
+        let rejected_transactions = [
+            (TransactionType::DEPOSIT, 3, Some(dec!(10.0))),
+            (TransactionType::WITHDRAWAL, 4, Some(dec!(10.0))),
+            (TransactionType::DISPUTE, 2, None),
+            (TransactionType::RESOLVE, 2, None),
+        ];
+        for (transaction_type, transaction_id, transaction_amount) in rejected_transactions {
+            let transaction_result = engine.process_transaction(&Transaction {
                 client_id: 1,
+                transaction_id,
+                transaction_type,
+                transaction_amount,
+                reason: None,
+                reason_code: None,
+            });
+            assert!(matches!(
+                transaction_result,
+                Err(ApplicationError::AccountLocked(_))
+            ));
+        }
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(20.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(0.0));
+    }
+
+    #[test]
+    fn test_lock_and_unlock_account() {
+        let mut engine = Engine::new();
+        let _ = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(100.0)),
+            reason: None,
+            reason_code: None,
+        });
+
+        // Administrative transactions require a reason
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            transaction_type: TransactionType::LOCK,
+            transaction_amount: None,
+            reason: Some(" ".to_string()),
+            reason_code: None,
+        });
+        assert!(matches!(
+            transaction_result,
+            Err(ApplicationError::InvalidData(_))
+        ));
+        assert!(!engine.store.get(1).unwrap().locked);
+
+        // Operator actions never open an account
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 2,
+            transaction_id: 2,
+            transaction_type: TransactionType::LOCK,
+            transaction_amount: None,
+            reason: Some("Court order".to_string()),
+            reason_code: None,
+        });
+        assert!(matches!(
+            transaction_result,
+            Err(ApplicationError::InvalidData(_))
+        ));
+        assert!(engine.store.get(2).is_none());
+
+        let lock = Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            transaction_type: TransactionType::LOCK,
+            transaction_amount: None,
+            reason: Some("Suspected account takeover".to_string()),
+            reason_code: None,
+        };
+        let transaction_result = engine.process_transaction(&lock);
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        assert!(engine.store.get(1).unwrap().locked);
+        let transaction_result = engine.process_transaction(&lock);
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Ignored { .. })
+        ));
+
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 3,
+            transaction_type: TransactionType::WITHDRAWAL,
+            transaction_amount: Some(dec!(10.0)),
+            reason: None,
+            reason_code: None,
+        });
+        assert!(matches!(
+            transaction_result,
+            Err(ApplicationError::AccountLocked(_))
+        ));
+
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 4,
+            transaction_type: TransactionType::UNLOCK,
+            transaction_amount: None,
+            reason: Some("Customer identity verified".to_string()),
+            reason_code: None,
+        });
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        assert!(!engine.store.get(1).unwrap().locked);
+
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 3,
+            transaction_type: TransactionType::WITHDRAWAL,
+            transaction_amount: Some(dec!(10.0)),
+            reason: None,
+            reason_code: None,
+        });
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(90.0));
+    }
+
+    #[test]
+    fn test_duplicate_transaction_rejected() {
+        let mut engine = Engine::new();
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(100.0)),
+            reason: None,
+            reason_code: None,
+        });
+        assert!(transaction_result.is_ok());
+
+        // Replay of the same id, from the same client and from another client
+        for client_id in [1, 2] {
+            let transaction_result = engine.process_transaction(&Transaction {
+                client_id,
                 transaction_id: 1,
                 transaction_type: TransactionType::DEPOSIT,
                 transaction_amount: Some(dec!(100.0)),
-            },
-        );
+                reason: None,
+                reason_code: None,
+            });
+            assert!(matches!(
+                transaction_result,
+                Err(ApplicationError::DuplicateTransaction(_))
+            ));
+        }
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(100.0));
+        assert!(engine.store.get(2).is_none());
+
+        // Disputes still reference the existing id
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DISPUTE,
+            transaction_amount: None,
+            reason: None,
+            reason_code: None,
+        });
         assert!(transaction_result.is_ok());
-        assert_eq!(account_data.get(&1).unwrap().available, dec!(100.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(100.0));
+    }
+
+    #[test]
+    fn test_dispute_unknown_transaction_rejected() {
+        let mut engine = Engine::new();
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(100.0)),
+            reason: None,
+            reason_code: None,
+        });
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
 
-        let transaction_result = process_transaction(
-            account_data,
-            &Transaction {
+        for transaction_type in [
+            TransactionType::DISPUTE,
+            TransactionType::RESOLVE,
+            TransactionType::CHARGEBACK,
+        ] {
+            let transaction_result = engine.process_transaction(&Transaction {
                 client_id: 1,
-                transaction_id: 2,
-                transaction_type: TransactionType::DEPOSIT,
-                transaction_amount: Some(dec!(31.5)),
-            },
+                transaction_id: 99,
+                transaction_type,
+                transaction_amount: None,
+                reason: None,
+                reason_code: None,
+            });
+            assert!(matches!(
+                transaction_result,
+                Err(ApplicationError::UnknownTransaction(_))
+            ));
+
+            // The transaction of another client is never applied, and no account is created
+            let transaction_result = engine.process_transaction(&Transaction {
+                client_id: 2,
+                transaction_id: 1,
+                transaction_type,
+                transaction_amount: None,
+                reason: None,
+                reason_code: None,
+            });
+            assert!(matches!(
+                transaction_result,
+                Err(ApplicationError::TransactionOwnership(_))
+            ));
+            assert!(engine.store.get(2).is_none());
+        }
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(100.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(0.0));
+
+        // A transaction of the client which is not in the referenced state is still ignored
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::RESOLVE,
+            transaction_amount: None,
+            reason: None,
+            reason_code: None,
+        });
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Ignored { .. })
+        ));
+    }
+
+    // Engine holding a client with a deposit of 100.0 as transaction 1 and a withdrawal of 30.0 as
+    // transaction 2
+    fn engine_with_withdrawal(dispute_policy: DisputePolicy) -> Engine {
+        let mut engine = Engine::new().with_dispute_policy(dispute_policy);
+        for (transaction_type, transaction_id, amount) in [
+            (TransactionType::DEPOSIT, 1, dec!(100.0)),
+            (TransactionType::WITHDRAWAL, 2, dec!(30.0)),
+        ] {
+            let transaction_result = engine.process_transaction(&Transaction {
+                client_id: 1,
+                transaction_id,
+                transaction_type,
+                transaction_amount: Some(amount),
+                reason: None,
+                reason_code: None,
+            });
+            assert!(matches!(
+                transaction_result,
+                Ok(TransactionOutcome::Applied)
+            ));
+        }
+        engine
+    }
+
+    fn referencing_transaction(
+        transaction_type: TransactionType,
+        transaction_id: TransactionIdentifier,
+    ) -> Transaction {
+        Transaction {
+            client_id: 1,
+            transaction_id,
+            transaction_type,
+            transaction_amount: None,
+            reason: None,
+            reason_code: None,
+        }
+    }
+
+    #[test]
+    fn test_dispute_withdrawal_deposits_only_policy() {
+        let mut engine = engine_with_withdrawal(DisputePolicy::DepositsOnly);
+
+        let transaction_result =
+            engine.process_transaction(&referencing_transaction(TransactionType::DISPUTE, 2));
+        assert!(matches!(
+            transaction_result,
+            Err(ApplicationError::DisputeNotPermitted(_))
+        ));
+        let account = engine.store.get(1).unwrap();
+        assert_eq!(account.available, dec!(70.0));
+        assert_eq!(account.held, dec!(0.0));
+        assert!(engine.store.settled_transaction(1, 2).is_some());
+
+        // Deposits are still disputed as before
+        let transaction_result =
+            engine.process_transaction(&referencing_transaction(TransactionType::DISPUTE, 1));
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(-30.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(100.0));
+    }
+
+    #[test]
+    fn test_dispute_withdrawal_reverses_funds() {
+        let mut engine = engine_with_withdrawal(DisputePolicy::DepositsAndWithdrawals);
+
+        // The withdrawn amount is held without reducing the available funds
+        let transaction_result =
+            engine.process_transaction(&referencing_transaction(TransactionType::DISPUTE, 2));
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(70.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(30.0));
+
+        // Resolving part of the dispute upholds that part of the withdrawal
+        let transaction_result = engine.process_transaction(&Transaction {
+            transaction_amount: Some(dec!(10.0)),
+            ..referencing_transaction(TransactionType::RESOLVE, 2)
+        });
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(70.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(20.0));
+
+        // A chargeback reverses the rest of the withdrawal, returning it to the available funds
+        let transaction_result =
+            engine.process_transaction(&referencing_transaction(TransactionType::CHARGEBACK, 2));
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        let account = engine.store.get(1).unwrap();
+        assert_eq!(account.available, dec!(90.0));
+        assert_eq!(account.held, dec!(0.0));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_partial_disputes() {
+        let mut engine = engine_with_withdrawal(DisputePolicy::DepositsOnly);
+        let partial = |transaction_type: TransactionType, amount| Transaction {
+            transaction_amount: Some(amount),
+            ..referencing_transaction(transaction_type, 1)
+        };
+
+        for amount in [dec!(30.0), dec!(50.0)] {
+            let transaction_result =
+                engine.process_transaction(&partial(TransactionType::DISPUTE, amount));
+            assert!(matches!(
+                transaction_result,
+                Ok(TransactionOutcome::Applied)
+            ));
+        }
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(-10.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(80.0));
+        assert_eq!(
+            engine
+                .store
+                .settled_transaction(1, 1)
+                .unwrap()
+                .transaction_amount,
+            Some(dec!(20.0))
+        );
+
+        // Disputes are limited to the undisputed remainder, and amounts must be positive
+        for amount in [dec!(30.0), dec!(0.0), dec!(-5.0)] {
+            let transaction_result =
+                engine.process_transaction(&partial(TransactionType::DISPUTE, amount));
+            assert!(matches!(
+                transaction_result,
+                Err(ApplicationError::InvalidData(_))
+            ));
+        }
+        let transaction_result =
+            engine.process_transaction(&partial(TransactionType::RESOLVE, dec!(90.0)));
+        assert!(matches!(
+            transaction_result,
+            Err(ApplicationError::InvalidData(_))
+        ));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(80.0));
+
+        // Resolves and chargebacks act on the given part of the disputed amount
+        let transaction_result =
+            engine.process_transaction(&partial(TransactionType::RESOLVE, dec!(30.0)));
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(20.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(50.0));
+        assert_eq!(
+            engine
+                .store
+                .settled_transaction(1, 1)
+                .unwrap()
+                .transaction_amount,
+            Some(dec!(50.0))
+        );
+
+        let transaction_result =
+            engine.process_transaction(&partial(TransactionType::CHARGEBACK, dec!(20.0)));
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        let account = engine.store.get(1).unwrap();
+        assert_eq!(account.available, dec!(20.0));
+        assert_eq!(account.held, dec!(30.0));
+        assert!(account.locked);
+        assert_eq!(
+            engine
+                .store
+                .disputed_transaction(1, 1)
+                .unwrap()
+                .transaction_amount,
+            Some(dec!(30.0))
         );
+
+        // The locked account can still settle the rest of the dispute, but nothing else
+        let transaction_result =
+            engine.process_transaction(&partial(TransactionType::RESOLVE, dec!(30.0)));
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        let account = engine.store.get(1).unwrap();
+        assert_eq!(account.available, dec!(50.0));
+        assert_eq!(account.held, dec!(0.0));
+        assert!(account.locked);
+        for transaction_type in [TransactionType::DISPUTE, TransactionType::RESOLVE] {
+            let transaction_result =
+                engine.process_transaction(&partial(transaction_type, dec!(10.0)));
+            assert!(matches!(
+                transaction_result,
+                Err(ApplicationError::AccountLocked(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_dispute_lifecycle() {
+        let mut engine = engine_with_withdrawal(DisputePolicy::DepositsOnly);
+        let _ = engine.process_transaction(&Transaction {
+            transaction_amount: Some(dec!(50.0)),
+            ..referencing_transaction(TransactionType::DEPOSIT, 3)
+        });
+
+        // Disputes of the same transaction add to one open dispute, keeping the first reason code
+        for (amount, reason_code) in [(dec!(40.0), Some("4837")), (dec!(10.0), Some("4853"))] {
+            let transaction_result = engine.process_transaction(&Transaction {
+                transaction_amount: Some(amount),
+                reason_code: reason_code.map(str::to_string),
+                ..referencing_transaction(TransactionType::DISPUTE, 1)
+            });
+            assert!(matches!(
+                transaction_result,
+                Ok(TransactionOutcome::Applied)
+            ));
+        }
+        let dispute = engine.disputes(1)[0].clone();
+        assert_eq!(dispute.state, DisputeState::Open);
+        assert_eq!(dispute.amount, dec!(50.0));
+        assert_eq!(dispute.reason_code.as_deref(), Some("4837"));
+
+        // The dispute ends once its whole amount is resolved, and cannot be settled again
+        let transaction_result =
+            engine.process_transaction(&referencing_transaction(TransactionType::RESOLVE, 1));
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        for transaction_type in [TransactionType::RESOLVE, TransactionType::CHARGEBACK] {
+            let transaction_result =
+                engine.process_transaction(&referencing_transaction(transaction_type, 1));
+            assert!(matches!(
+                transaction_result,
+                Err(ApplicationError::DisputeTransition(_))
+            ));
+        }
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(120.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(0.0));
+
+        // An expiry releases the whole remaining disputed amount
+        let _ = engine.process_transaction(&referencing_transaction(TransactionType::DISPUTE, 3));
+        let transaction_result = engine.process_transaction(&Transaction {
+            transaction_amount: Some(dec!(5.0)),
+            ..referencing_transaction(TransactionType::EXPIRE, 3)
+        });
+        assert!(matches!(
+            transaction_result,
+            Err(ApplicationError::InvalidData(_))
+        ));
+        let transaction_result =
+            engine.process_transaction(&referencing_transaction(TransactionType::EXPIRE, 3));
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(120.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(0.0));
+
+        let disputes = engine.disputes(1);
+        assert_eq!(
+            disputes
+                .iter()
+                .map(|dispute| (dispute.transaction_id, dispute.sequence, dispute.state))
+                .collect::<Vec<_>>(),
+            [
+                (1, 1, DisputeState::Resolved),
+                (3, 2, DisputeState::Expired)
+            ]
+        );
+        assert_eq!(disputes[0].resolved, dec!(50.0));
+        assert_eq!(disputes[1].outstanding(), dec!(50.0));
+    }
+
+    #[test]
+    fn test_dispute_remainder_after_dispute_ends() {
+        let mut engine = engine_with_withdrawal(DisputePolicy::DepositsOnly);
+        let partial = |transaction_type: TransactionType, amount| Transaction {
+            transaction_amount: amount,
+            ..referencing_transaction(transaction_type, 1)
+        };
+
+        // The undisputed remainder can be disputed again once the first dispute has ended
+        for transaction in [
+            partial(TransactionType::DISPUTE, Some(dec!(30.0))),
+            partial(TransactionType::RESOLVE, Some(dec!(30.0))),
+            partial(TransactionType::DISPUTE, Some(dec!(20.0))),
+        ] {
+            let transaction_result = engine.process_transaction(&transaction);
+            assert!(matches!(
+                transaction_result,
+                Ok(TransactionOutcome::Applied)
+            ));
+        }
+        let disputes = engine.disputes(1);
+        assert_eq!(
+            disputes
+                .iter()
+                .map(|dispute| (dispute.sequence, dispute.state, dispute.amount))
+                .collect::<Vec<_>>(),
+            [
+                (1, DisputeState::Resolved, dec!(30.0)),
+                (2, DisputeState::Open, dec!(20.0))
+            ]
+        );
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(20.0));
+
+        // Only the remainder can be disputed, nothing remains once charged back
+        let transaction_result =
+            engine.process_transaction(&partial(TransactionType::DISPUTE, Some(dec!(90.0))));
+        assert!(matches!(
+            transaction_result,
+            Err(ApplicationError::InvalidData(_))
+        ));
+        for transaction in [
+            partial(TransactionType::DISPUTE, None),
+            partial(TransactionType::CHARGEBACK, None),
+        ] {
+            let transaction_result = engine.process_transaction(&transaction);
+            assert!(matches!(
+                transaction_result,
+                Ok(TransactionOutcome::Applied)
+            ));
+        }
+        let transaction_result = engine.process_transaction(&Transaction {
+            reason: Some("Chargeback reviewed".to_string()),
+            ..referencing_transaction(TransactionType::UNLOCK, 4)
+        });
+        assert!(matches!(
+            transaction_result,
+            Ok(TransactionOutcome::Applied)
+        ));
+        let transaction_result =
+            engine.process_transaction(&partial(TransactionType::DISPUTE, None));
+        assert!(matches!(
+            transaction_result,
+            Err(ApplicationError::DisputeTransition(_))
+        ));
+        assert_eq!(engine.disputes(1).len(), 2);
+        assert_eq!(engine.disputes(1)[1].charged_back, dec!(100.0));
+    }
+
+    #[test]
+    fn test_dispute_end_state() {
+        let mut engine = engine_with_withdrawal(DisputePolicy::DepositsOnly);
+        let _ = engine.process_transaction(&Transaction {
+            transaction_amount: Some(dec!(50.0)),
+            ..referencing_transaction(TransactionType::DEPOSIT, 3)
+        });
+        let partial = |transaction_type: TransactionType, transaction_id, amount| Transaction {
+            transaction_amount: amount,
+            ..referencing_transaction(transaction_type, transaction_id)
+        };
+
+        // The end state follows how the disputed amount was settled, not the last transaction
+        for transaction in [
+            partial(TransactionType::DISPUTE, 3, None),
+            partial(TransactionType::RESOLVE, 3, Some(dec!(20.0))),
+            partial(TransactionType::EXPIRE, 3, None),
+            partial(TransactionType::DISPUTE, 1, Some(dec!(60.0))),
+            partial(TransactionType::CHARGEBACK, 1, Some(dec!(10.0))),
+            partial(TransactionType::RESOLVE, 1, Some(dec!(50.0))),
+        ] {
+            let transaction_result = engine.process_transaction(&transaction);
+            assert!(matches!(
+                transaction_result,
+                Ok(TransactionOutcome::Applied)
+            ));
+        }
+
+        let disputes = engine.disputes(1);
+        assert_eq!(
+            disputes
+                .iter()
+                .map(|dispute| (
+                    dispute.transaction_id,
+                    dispute.state,
+                    dispute.resolved,
+                    dispute.charged_back,
+                    dispute.outstanding()
+                ))
+                .collect::<Vec<_>>(),
+            [
+                (3, DisputeState::Expired, dec!(20.0), dec!(0.0), dec!(30.0)),
+                (
+                    1,
+                    DisputeState::ChargedBack,
+                    dec!(50.0),
+                    dec!(10.0),
+                    dec!(0.0)
+                )
+            ]
+        );
+        let account = engine.store.get(1).unwrap();
+        assert_eq!(account.available, dec!(110.0));
+        assert_eq!(account.held, dec!(0.0));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn test_dispute_resolve_transaction_success() {
+        let mut engine = Engine::new();
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(100.0)),
+            reason: None,
+            reason_code: None,
+        });
         assert!(transaction_result.is_ok());
-        assert_eq!(account_data.get(&1).unwrap().available, dec!(131.5));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(100.0));
+
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(31.5)),
+            reason: None,
+            reason_code: None,
+        });
+        assert!(transaction_result.is_ok());
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(131.5));
 
         // Dispute transaction 2
-        let transaction_result = process_transaction(
-            account_data,
-            &Transaction {
-                client_id: 1,
-                transaction_id: 2,
-                transaction_type: TransactionType::DISPUTE,
-                transaction_amount: None,
-            },
-        );
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            transaction_type: TransactionType::DISPUTE,
+            transaction_amount: None,
+            reason: None,
+            reason_code: None,
+        });
         assert!(transaction_result.is_ok());
-        assert_eq!(account_data.get(&1).unwrap().held, dec!(31.5));
-        assert_eq!(account_data.get(&1).unwrap().available, dec!(100.0));
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(31.5));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(100.0));
 
         // Resolve the dispute
-        let transaction_result = process_transaction(
-            account_data,
-            &Transaction {
-                client_id: 1,
-                transaction_id: 2,
-                transaction_type: TransactionType::RESOLVE,
-                transaction_amount: None,
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 2,
+            transaction_type: TransactionType::RESOLVE,
+            transaction_amount: None,
+            reason: None,
+            reason_code: None,
+        });
+        assert!(transaction_result.is_ok());
+        assert_eq!(engine.store.get(1).unwrap().held, dec!(0.0));
+        assert_eq!(engine.store.get(1).unwrap().available, dec!(131.5));
+    }
+
+    #[test]
+    fn test_failed_commit_rolls_back_account() {
+        let mut engine = Engine::with_store(FailingStore::default());
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(40.0)),
+            reason: None,
+            reason_code: None,
+        });
+        assert!(transaction_result.is_ok());
+
+        engine.store.fail_commits = true;
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 1,
+            transaction_id: 1,
+            transaction_type: TransactionType::DISPUTE,
+            transaction_amount: None,
+            reason: None,
+            reason_code: None,
+        });
+        assert!(matches!(
+            transaction_result,
+            Err(ApplicationError::Io { .. })
+        ));
+        let account = engine.store.get(1).unwrap();
+        assert_eq!(account.available, dec!(40.0));
+        assert_eq!(account.held, dec!(0.0));
+        assert!(engine.store.settled_transaction(1, 1).is_some());
+        assert!(engine.store.disputed_transaction(1, 1).is_none());
+        assert!(engine.disputes(1).is_empty());
+
+        // A new account is dropped along with the failed transaction
+        let transaction_result = engine.process_transaction(&Transaction {
+            client_id: 2,
+            transaction_id: 2,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(5.0)),
+            reason: None,
+            reason_code: None,
+        });
+        assert!(transaction_result.is_err());
+        assert!(engine.store.get(2).is_none());
+        assert!(!engine.store.contains_transaction_id(2));
+    }
+
+    #[test]
+    fn test_snapshot_keeps_owner_of_charged_back_transaction() {
+        let snapshot_path = env::temp_dir().join(format!(
+            "transaction_engine_owner_snapshot_{}.json",
+            std::process::id()
+        ));
+        let snapshot_file_str = snapshot_path.to_str().unwrap();
+
+        // The deposit is fully charged back, leaving it in no transaction log
+        let mut engine = Engine::new();
+        for transaction in [
+            Transaction {
+                transaction_amount: Some(dec!(1.0)),
+                ..referencing_transaction(TransactionType::DEPOSIT, 1)
             },
+            referencing_transaction(TransactionType::DISPUTE, 1),
+            referencing_transaction(TransactionType::CHARGEBACK, 1),
+        ] {
+            let transaction_result = engine.process_transaction(&transaction);
+            assert!(matches!(
+                transaction_result,
+                Ok(TransactionOutcome::Applied)
+            ));
+        }
+        engine.save_snapshot(snapshot_file_str).unwrap();
+
+        let mut restored_engine = Engine::new();
+        restored_engine.load_snapshot(snapshot_file_str).unwrap();
+        fs::remove_file(&snapshot_path).unwrap();
+        let transaction_result = restored_engine.process_transaction(&Transaction {
+            client_id: 2,
+            ..referencing_transaction(TransactionType::DISPUTE, 1)
+        });
+        assert!(matches!(
+            transaction_result,
+            Err(ApplicationError::TransactionOwnership(_))
+        ));
+    }
+
+    #[test]
+    fn test_failed_commit_removes_journal_entry() {
+        let journal_path = env::temp_dir().join(format!(
+            "transaction_engine_failed_commit_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&journal_path);
+        let deposit = |transaction_id: TransactionIdentifier| Transaction {
+            client_id: 1,
+            transaction_id,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(10.0)),
+            reason: None,
+            reason_code: None,
+        };
+
+        let mut engine = Engine::with_store(FailingStore::default())
+            .with_journal(Journal::open(&journal_path).unwrap());
+        assert!(engine.process_transaction(&deposit(1)).is_ok());
+        engine.store.fail_commits = true;
+        assert!(engine.process_transaction(&deposit(2)).is_err());
+        engine.store.fail_commits = false;
+        assert!(engine.process_transaction(&deposit(3)).is_ok());
+
+        // Replay only applies the committed transactions
+        let mut replayed_engine = Engine::new();
+        assert_eq!(
+            journal::replay(journal_path.to_str().unwrap(), &mut replayed_engine).unwrap(),
+            2
         );
-        assert!(transaction_result.is_ok());
-        assert_eq!(account_data.get(&1).unwrap().held, dec!(0.0));
-        assert_eq!(account_data.get(&1).unwrap().available, dec!(131.5));
+        assert_eq!(replayed_engine.account(1).unwrap().available, dec!(20.0));
+        assert!(!replayed_engine.store.contains_transaction_id(2));
+
+        fs::remove_file(&journal_path).unwrap();
     }
 }
@@ -1,16 +1,16 @@
 #![allow(clippy::redundant_field_names)]
 #![allow(clippy::upper_case_acronyms)]
 
-mod types;
-
 use clap::Parser;
 use csv::Writer;
 use rand::distributions::Standard;
 use rand::prelude::ThreadRng;
-use rand::seq::IteratorRandom;
 use rand::{thread_rng, Rng};
 use rust_decimal::Decimal;
-use types::{ClientIdentifier, Transaction, TransactionIdentifier, TransactionType, ValueAmount};
+use transaction_engine::types::{
+    errors::ApplicationError, ClientIdentifier, Transaction, TransactionIdentifier,
+    TransactionType, ValueAmount,
+};
 
 /// Command line arguments
 #[derive(Parser)]
@@ -42,15 +42,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut rng: ThreadRng = thread_rng();
 
     for _ in 0..cli.count {
-        let transaction_type: TransactionType = *type_variants.iter().choose(&mut rng).unwrap();
+        let transaction_type: TransactionType =
+            type_variants[rng.gen_range(0..type_variants.len())];
         let client_identifier: ClientIdentifier = rng.gen_range(1..30);
         let transaction_identifier: TransactionIdentifier = rng.sample(Standard);
         let mut transaction_amount: Option<ValueAmount> = None;
         if transaction_type == TransactionType::DEPOSIT
             || transaction_type == TransactionType::WITHDRAWAL
         {
-            transaction_amount =
-                Some(Decimal::from_f64_retain(rng.gen_range(10.0..1000000.0)).unwrap());
+            let random_amount: f64 = rng.gen_range(10.0..1000000.0);
+            let amount: Decimal =
+                Decimal::try_from(random_amount).map_err(|err| ApplicationError::Conversion {
+                    context: format!(
+                        "Unable to convert [{}] to a transaction amount",
+                        random_amount
+                    ),
+                    source: err,
+                })?;
+            transaction_amount = Some(amount.round_dp(4));
         }
 
         let record: Transaction = Transaction {
@@ -58,6 +67,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             client_id: client_identifier,
             transaction_id: transaction_identifier,
             transaction_amount: transaction_amount,
+            reason: None,
+            reason_code: None,
         };
 
         wtr.serialize(record)?;
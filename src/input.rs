@@ -0,0 +1,692 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader},
+    iter,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use csv::{ByteRecord, Reader, ReaderBuilder, StringRecord};
+use log::debug;
+use serde::Serialize;
+
+use crate::types::{errors::ApplicationError, Transaction};
+
+/// Input path which reads transactions from stdin
+pub const STDIN_PATH: &str = "-";
+
+// Field names of a transaction record, in the default column order
+const TRANSACTION_FIELDS: [&str; 6] = ["type", "client", "tx", "amount", "reason", "reason_code"];
+
+// Transaction fields which may be absent from the input, only some transaction types carry them
+const OPTIONAL_FIELDS: [&str; 3] = ["amount", "reason", "reason_code"];
+
+// Position of each transaction field within a record, in the order of TRANSACTION_FIELDS
+type FieldPositions = [Option<usize>; 6];
+
+/// Format of input files
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InputFormat {
+    /// CSV, laid out according to [`CsvLayout`]
+    #[default]
+    Csv,
+    /// One JSON object per transaction, one transaction per line
+    Ndjson,
+}
+
+impl InputFormat {
+    // Extensions of the files read from input directories
+    fn file_extensions(&self) -> &'static [&'static str] {
+        match self {
+            InputFormat::Csv => &["csv"],
+            InputFormat::Ndjson => &["ndjson", "jsonl"],
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = ApplicationError;
+
+    fn from_str(format_str: &str) -> Result<Self, Self::Err> {
+        match format_str {
+            "csv" => Ok(InputFormat::Csv),
+            "ndjson" => Ok(InputFormat::Ndjson),
+            _ => Err(ApplicationError::InvalidData(format!(
+                "Input format [{}] is not one of csv, ndjson",
+                format_str
+            ))),
+        }
+    }
+}
+
+/// Layout of CSV input files
+#[derive(Clone, Debug)]
+pub struct CsvLayout {
+    /// Whether the first record of each file is a header naming the columns. Without headers the
+    /// columns are identified by position.
+    pub has_headers: bool,
+    /// Field delimiter
+    pub delimiter: u8,
+    /// Columns holding each transaction field, when they differ from the defaults
+    pub column_mapping: ColumnMapping,
+}
+
+impl Default for CsvLayout {
+    fn default() -> Self {
+        CsvLayout {
+            has_headers: true,
+            delimiter: b',',
+            column_mapping: ColumnMapping::default(),
+        }
+    }
+}
+
+/// Source column of each transaction field.
+///
+/// Columns are named by header for input with headers, and by position starting from 1 for input
+/// without headers. Fields which are not mapped use the default header name, or the default
+/// position in the order `type,client,tx,amount,reason,reason_code`.
+///
+/// Parsed from a comma separated list of `field=column` pairs, e.g. `client=client_id,tx=txn_id`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColumnMapping {
+    pub transaction_type: Option<String>,
+    pub client_id: Option<String>,
+    pub transaction_id: Option<String>,
+    pub transaction_amount: Option<String>,
+    pub reason: Option<String>,
+    pub reason_code: Option<String>,
+}
+
+impl ColumnMapping {
+    // Mapped columns in the order of TRANSACTION_FIELDS
+    fn columns(&self) -> [Option<&str>; 6] {
+        [
+            self.transaction_type.as_deref(),
+            self.client_id.as_deref(),
+            self.transaction_id.as_deref(),
+            self.transaction_amount.as_deref(),
+            self.reason.as_deref(),
+            self.reason_code.as_deref(),
+        ]
+    }
+}
+
+impl FromStr for ColumnMapping {
+    type Err = ApplicationError;
+
+    fn from_str(mapping_str: &str) -> Result<Self, Self::Err> {
+        let mut column_mapping = ColumnMapping::default();
+        for pair in mapping_str.split(',') {
+            let Some((field, column)) = pair.split_once('=') else {
+                return Err(ApplicationError::InvalidData(format!(
+                    "Column mapping [{}] is not of the form field=column",
+                    pair
+                )));
+            };
+            let column: Option<String> = Some(column.trim().to_string());
+            match field.trim() {
+                "type" => column_mapping.transaction_type = column,
+                "client" => column_mapping.client_id = column,
+                "tx" => column_mapping.transaction_id = column,
+                "amount" => column_mapping.transaction_amount = column,
+                "reason" => column_mapping.reason = column,
+                "reason_code" => column_mapping.reason_code = column,
+                field => {
+                    return Err(ApplicationError::InvalidData(format!(
+                        "Column mapping field [{}] is not one of {}",
+                        field,
+                        TRANSACTION_FIELDS.join(", ")
+                    )))
+                }
+            }
+        }
+
+        Ok(column_mapping)
+    }
+}
+
+/// Expand directories into the files of the input format they contain, in file name order. Other
+/// paths are kept as given, in order.
+pub(crate) fn resolve_input_files(
+    input_paths: &[String],
+    input_format: InputFormat,
+) -> Result<Vec<String>, ApplicationError> {
+    let mut input_files: Vec<String> = Vec::new();
+    for input_path in input_paths {
+        if input_path == STDIN_PATH || !Path::new(input_path).is_dir() {
+            input_files.push(input_path.clone());
+            continue;
+        }
+
+        let directory_error = |err: io::Error| {
+            ApplicationError::io(
+                format!("Unable to read input directory [{}]", input_path),
+                err,
+            )
+        };
+        let mut directory_files: Vec<PathBuf> = Vec::new();
+        for directory_entry in fs::read_dir(input_path).map_err(directory_error)? {
+            let file_path: PathBuf = directory_entry.map_err(directory_error)?.path();
+            let is_input_file: bool = file_path.extension().is_some_and(|extension| {
+                input_format
+                    .file_extensions()
+                    .iter()
+                    .any(|input_extension| extension == *input_extension)
+            });
+            if file_path.is_file() && is_input_file {
+                directory_files.push(file_path);
+            }
+        }
+        if directory_files.is_empty() {
+            return Err(ApplicationError::InvalidData(format!(
+                "Input directory [{}] does not contain any {} files",
+                input_path,
+                input_format.file_extensions().join("/")
+            )));
+        }
+        directory_files.sort();
+        input_files.extend(
+            directory_files
+                .into_iter()
+                .map(|file_path| file_path.to_string_lossy().into_owned()),
+        );
+    }
+
+    Ok(input_files)
+}
+
+/// Failure to read a transaction from an input file
+#[derive(Debug)]
+pub(crate) enum InputError {
+    /// The input file could not be opened or read, no further records can be read from it
+    Unreadable(ApplicationError),
+    /// A single record could not be read as a transaction
+    Malformed(MalformedRecord),
+}
+
+/// Input record which could not be read as a transaction, along with its location in the input
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MalformedRecord {
+    pub file: String,
+    pub line: u64,
+    /// Column of the invalid field numbered from 1, when the error relates to a single field
+    pub column: Option<u64>,
+    #[serde(rename = "error")]
+    pub message: String,
+    /// Content of the record as read, CSV fields are joined with the delimiter
+    #[serde(rename = "record")]
+    pub raw_record: String,
+}
+
+impl From<MalformedRecord> for ApplicationError {
+    fn from(record: MalformedRecord) -> Self {
+        ApplicationError::MalformedRecord {
+            file: record.file,
+            line: record.line,
+            column: record.column,
+            message: record.message,
+        }
+    }
+}
+
+/// Transactions of every input file in order, or the reason a record could not be read. Each file
+/// is only opened once the previous one is exhausted, a file which cannot be opened is reported as
+/// unreadable.
+pub(crate) fn read_input_files<'a>(
+    input_files: &'a [String],
+    input_format: InputFormat,
+    layout: &'a CsvLayout,
+) -> impl Iterator<Item = Result<Transaction, InputError>> + 'a {
+    input_files.iter().flat_map(
+        move |input_file| -> Box<dyn Iterator<Item = Result<Transaction, InputError>>> {
+            let transactions = match input_format {
+                InputFormat::Csv => {
+                    open_transaction_reader(input_file, layout).map(|(reader, positions)| {
+                        Box::new(read_transactions(
+                            input_file.clone(),
+                            reader,
+                            positions,
+                            layout.delimiter,
+                        )) as Box<dyn Iterator<Item = _>>
+                    })
+                }
+                InputFormat::Ndjson => open_input(input_file).map(|data_source| {
+                    Box::new(read_json_transactions(input_file.clone(), data_source))
+                        as Box<dyn Iterator<Item = _>>
+                }),
+            };
+            transactions
+                .unwrap_or_else(|err| Box::new(iter::once(Err(InputError::Unreadable(err)))))
+        },
+    )
+}
+
+// Deserialize transactions from JSON lines one line at a time, skipping blank lines
+fn read_json_transactions<R: BufRead>(
+    data_file_str: String,
+    data_source: R,
+) -> impl Iterator<Item = Result<Transaction, InputError>> {
+    data_source
+        .lines()
+        .zip(1..)
+        .filter_map(move |(line_result, line_number)| {
+            let line: String = match line_result {
+                Ok(line) => line,
+                Err(err) => {
+                    return Some(Err(InputError::Unreadable(ApplicationError::io(
+                        format!("Error reading batch data file [{}]", data_file_str),
+                        err,
+                    ))))
+                }
+            };
+            if line.trim().is_empty() {
+                return None;
+            }
+            match serde_json::from_str::<Transaction>(&line) {
+                Ok(transaction) => {
+                    debug!("Transaction read: \n{:?}", transaction);
+                    Some(Ok(transaction))
+                }
+                Err(err) => {
+                    // The position of the error within the line is reported as the column
+                    let position_suffix =
+                        format!(" at line {} column {}", err.line(), err.column());
+                    let message: String = err.to_string();
+                    Some(Err(InputError::Malformed(MalformedRecord {
+                        file: data_file_str.clone(),
+                        line: line_number,
+                        column: Some(err.column() as u64).filter(|column| *column > 0),
+                        message: message
+                            .strip_suffix(&position_suffix)
+                            .unwrap_or(&message)
+                            .to_string(),
+                        raw_record: line,
+                    })))
+                }
+            }
+        })
+}
+
+// Deserialize transactions from the reader one record at a time. Each record is rearranged into
+// the default column order before it is deserialized.
+fn read_transactions<R: io::Read>(
+    data_file_str: String,
+    mut transaction_reader: Reader<R>,
+    positions: FieldPositions,
+    delimiter: u8,
+) -> impl Iterator<Item = Result<Transaction, InputError>> {
+    // The reader is flexible so a record with the wrong number of fields can be reported with its
+    // raw content. Every record must have as many fields as the header. Without a header, columns
+    // are positional and missing trailing optional fields are read as empty.
+    let expected_len: Option<usize> = match transaction_reader.has_headers() {
+        true => transaction_reader
+            .byte_headers()
+            .ok()
+            .map(ByteRecord::len)
+            .filter(|len| *len > 0),
+        false => None,
+    };
+    let field_names = StringRecord::from(TRANSACTION_FIELDS.to_vec());
+    let unreadable = {
+        let data_file_str: String = data_file_str.clone();
+        move |err: io::Error| {
+            InputError::Unreadable(ApplicationError::io(
+                format!("Error reading batch data file [{}]", data_file_str),
+                err,
+            ))
+        }
+    };
+    let malformed = move |line: u64, column: Option<u64>, message: String, raw_record: String| {
+        InputError::Malformed(MalformedRecord {
+            file: data_file_str.clone(),
+            line,
+            column,
+            message,
+            raw_record,
+        })
+    };
+
+    transaction_reader
+        .into_byte_records()
+        .map(move |csv_result| {
+            let byte_record: ByteRecord = csv_result.map_err(|err| {
+                let line: u64 = err.position().map_or(0, |position| position.line());
+                let message: String = err.to_string();
+                match err.into_kind() {
+                    csv::ErrorKind::Io(err) => unreadable(err),
+                    _ => malformed(line, None, message, String::new()),
+                }
+            })?;
+            let line: u64 = byte_record.position().map_or(0, |position| position.line());
+            if let Some(expected_len) = expected_len.filter(|len| *len != byte_record.len()) {
+                return Err(malformed(
+                    line,
+                    None,
+                    format!(
+                        "Record has {} fields, expected {}",
+                        byte_record.len(),
+                        expected_len
+                    ),
+                    join_fields(byte_record.iter(), delimiter),
+                ));
+            }
+            let record: StringRecord =
+                StringRecord::from_byte_record(byte_record).map_err(|err| {
+                    let column: Option<u64> = Some(err.utf8_error().field() as u64 + 1);
+                    let message: String = err.utf8_error().to_string();
+                    malformed(
+                        line,
+                        column,
+                        message,
+                        join_fields(err.into_byte_record().iter(), delimiter),
+                    )
+                })?;
+
+            let transaction_record: StringRecord = positions
+                .iter()
+                .map(|position| position.and_then(|position| record.get(position)))
+                .map(Option::unwrap_or_default)
+                .collect();
+            match transaction_record.deserialize::<Transaction>(Some(&field_names)) {
+                Ok(transaction) => {
+                    debug!("Transaction read: \n{:?}", transaction);
+                    Ok(transaction)
+                }
+                Err(err) => {
+                    let (field, message) = match err.kind() {
+                        csv::ErrorKind::Deserialize { err, .. } => (
+                            err.field()
+                                .map(|field| field as usize)
+                                .or_else(|| invalid_field(&transaction_record, &field_names)),
+                            err.kind().to_string(),
+                        ),
+                        _ => (None, err.to_string()),
+                    };
+                    // The field index refers to the rearranged record, map it back to the input
+                    let column: Option<u64> = field
+                        .and_then(|field| positions[field])
+                        .map(|position| position as u64 + 1);
+                    Err(malformed(
+                        line,
+                        column,
+                        message,
+                        join_fields(record.as_byte_record().iter(), delimiter),
+                    ))
+                }
+            }
+        })
+}
+
+// Locate the field of a record which cannot be deserialized, by checking each field in turn
+// alongside valid values for the other fields. Errors raised by the field types themselves do not
+// carry the field they relate to.
+fn invalid_field(transaction_record: &StringRecord, field_names: &StringRecord) -> Option<usize> {
+    const VALID_FIELDS: [&str; 6] = ["deposit", "1", "1", "1", "", ""];
+    (0..VALID_FIELDS.len()).find(|invalid_index| {
+        VALID_FIELDS
+            .iter()
+            .enumerate()
+            .map(|(index, valid_field)| match index == *invalid_index {
+                true => transaction_record.get(index).unwrap_or_default(),
+                false => valid_field,
+            })
+            .collect::<StringRecord>()
+            .deserialize::<Transaction>(Some(field_names))
+            .is_err()
+    })
+}
+
+// Raw content of a CSV record, its fields joined with the delimiter
+fn join_fields<'a, I: Iterator<Item = &'a [u8]>>(fields: I, delimiter: u8) -> String {
+    fields
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(&char::from(delimiter).to_string())
+}
+
+// Open an input file, or stdin
+fn open_input(data_file_str: &str) -> Result<Box<dyn BufRead>, ApplicationError> {
+    if data_file_str == STDIN_PATH {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+
+    let data_file: File = File::open(data_file_str).map_err(|io_err| {
+        ApplicationError::io(
+            format!("Error reading batch data file [{:?}]", data_file_str),
+            io_err,
+        )
+    })?;
+
+    Ok(Box::new(BufReader::new(data_file)))
+}
+
+// Open a CSV input file, or stdin, and locate the column of each transaction field
+fn open_transaction_reader(
+    data_file_str: &str,
+    layout: &CsvLayout,
+) -> Result<(Reader<Box<dyn BufRead>>, FieldPositions), ApplicationError> {
+    let data_source: Box<dyn BufRead> = open_input(data_file_str)?;
+
+    let mut transaction_reader = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .has_headers(layout.has_headers)
+        .delimiter(layout.delimiter)
+        .from_reader(data_source);
+
+    let positions = if layout.has_headers {
+        let headers: StringRecord = transaction_reader.headers().cloned().map_err(|err| {
+            ApplicationError::csv(
+                format!(
+                    "Error reading header of batch data file [{}]",
+                    data_file_str
+                ),
+                err,
+            )
+        })?;
+        header_positions(data_file_str, &headers, &layout.column_mapping)?
+    } else {
+        column_positions(&layout.column_mapping)?
+    };
+    debug!(
+        "Transaction field positions for [{}]: {:?}",
+        data_file_str, positions
+    );
+
+    Ok((transaction_reader, positions))
+}
+
+// Position of each transaction field within the header. The amount, reason and reason code
+// columns are optional, as only some transaction types carry an amount, only administrative
+// transactions carry a reason and only disputes carry a reason code.
+fn header_positions(
+    data_file_str: &str,
+    headers: &StringRecord,
+    column_mapping: &ColumnMapping,
+) -> Result<FieldPositions, ApplicationError> {
+    let mut positions: FieldPositions = [None; 6];
+    for (index, (field, column)) in TRANSACTION_FIELDS
+        .iter()
+        .zip(column_mapping.columns())
+        .enumerate()
+    {
+        let column: &str = column.unwrap_or(field);
+        positions[index] = headers.iter().position(|header| header == column);
+        if positions[index].is_none() && !OPTIONAL_FIELDS.contains(field) {
+            return Err(ApplicationError::InvalidData(format!(
+                "Batch data file [{}] has no [{}] column for transaction field [{}]",
+                data_file_str, column, field
+            )));
+        }
+    }
+
+    Ok(positions)
+}
+
+// Position of each transaction field for input without headers, mapped columns are numbered from 1
+fn column_positions(column_mapping: &ColumnMapping) -> Result<FieldPositions, ApplicationError> {
+    let mut positions: FieldPositions = [None; 6];
+    for (index, (field, column)) in TRANSACTION_FIELDS
+        .iter()
+        .zip(column_mapping.columns())
+        .enumerate()
+    {
+        positions[index] = match column {
+            None => Some(index),
+            Some(column) => match column.parse::<usize>() {
+                Ok(column_number) if column_number > 0 => Some(column_number - 1),
+                _ => {
+                    return Err(ApplicationError::InvalidData(format!(
+                        "Column [{}] for transaction field [{}] must be a position starting from 1 for input without headers",
+                        column, field
+                    )))
+                }
+            },
+        };
+    }
+
+    Ok(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::{ReaderBuilder, StringRecord};
+    use rust_decimal_macros::dec;
+
+    use crate::{
+        input::{
+            column_positions, header_positions, read_json_transactions, read_transactions,
+            ColumnMapping, InputError,
+        },
+        types::{errors::ApplicationError, Transaction, TransactionType},
+    };
+
+    #[test]
+    fn test_column_mapping_positions() {
+        let column_mapping: ColumnMapping =
+            "client=client_id, tx=txn_id, reason=note".parse().unwrap();
+        assert_eq!(column_mapping.client_id.as_deref(), Some("client_id"));
+        assert_eq!(column_mapping.transaction_id.as_deref(), Some("txn_id"));
+        assert!(column_mapping.transaction_type.is_none());
+
+        let headers = StringRecord::from(vec!["txn_id", "type", "client_id", "note"]);
+        assert_eq!(
+            header_positions("partner.csv", &headers, &column_mapping).unwrap(),
+            [Some(1), Some(2), Some(0), None, Some(3), None]
+        );
+        assert!(matches!(
+            header_positions("partner.csv", &headers, &ColumnMapping::default()),
+            Err(ApplicationError::InvalidData(_))
+        ));
+
+        // Input without headers uses positions from 1, unmapped fields keep the default order
+        let column_mapping: ColumnMapping = "amount=5,reason=4".parse().unwrap();
+        assert_eq!(
+            column_positions(&column_mapping).unwrap(),
+            [Some(0), Some(1), Some(2), Some(4), Some(3), Some(5)]
+        );
+        assert!(column_positions(&"tx=txn_id".parse().unwrap()).is_err());
+        assert!("client".parse::<ColumnMapping>().is_err());
+        assert!("account=client_id".parse::<ColumnMapping>().is_err());
+    }
+
+    #[test]
+    fn test_read_json_transactions() {
+        let input = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"2.5\"}\n\
+            \n\
+            {\"type\":\"unknown\",\"client\":1,\"tx\":2}\n\
+            {\"type\":\"dispute\",\"client\":1,\"tx\":1}\n";
+        let records: Vec<Result<Transaction, InputError>> =
+            read_json_transactions("batch.ndjson".to_string(), input.as_bytes()).collect();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0].as_ref().unwrap(),
+            &Transaction {
+                client_id: 1,
+                transaction_id: 1,
+                transaction_type: TransactionType::DEPOSIT,
+                transaction_amount: Some(dec!(2.5)),
+                reason: None,
+                reason_code: None,
+            }
+        );
+        let Err(InputError::Malformed(malformed_record)) = &records[1] else {
+            panic!("Expected a malformed record, found {:?}", records[1]);
+        };
+        assert_eq!(malformed_record.file, "batch.ndjson");
+        assert_eq!(malformed_record.line, 3);
+        assert!(malformed_record.column.is_some());
+        assert!(malformed_record
+            .message
+            .starts_with("unknown variant `unknown`"));
+        assert_eq!(
+            malformed_record.raw_record,
+            "{\"type\":\"unknown\",\"client\":1,\"tx\":2}"
+        );
+        assert_eq!(
+            records[2].as_ref().unwrap().transaction_type,
+            TransactionType::DISPUTE
+        );
+    }
+
+    #[test]
+    fn test_read_transactions_reports_malformed_records() {
+        let input = "tx;client;type;amount\n1;4;deposit;2.5\n2;four;deposit;1.0\n3;4\n";
+        let transaction_reader = ReaderBuilder::new()
+            .delimiter(b';')
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        let records: Vec<Result<Transaction, InputError>> = read_transactions(
+            "batch.csv".to_string(),
+            transaction_reader,
+            [Some(2), Some(1), Some(0), Some(3), None, None],
+            b';',
+        )
+        .collect();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].as_ref().unwrap().client_id, 4);
+        let Err(InputError::Malformed(malformed_record)) = &records[1] else {
+            panic!("Expected a malformed record, found {:?}", records[1]);
+        };
+        assert_eq!(
+            ApplicationError::from(malformed_record.clone()).to_string(),
+            "File [batch.csv] line 3 column 2 - invalid digit found in string"
+        );
+        assert_eq!(malformed_record.raw_record, "2;four;deposit;1.0");
+        let Err(InputError::Malformed(malformed_record)) = &records[2] else {
+            panic!("Expected a malformed record, found {:?}", records[2]);
+        };
+        assert_eq!(malformed_record.line, 4);
+        assert_eq!(malformed_record.column, None);
+        assert_eq!(malformed_record.message, "Record has 2 fields, expected 4");
+        assert_eq!(malformed_record.raw_record, "3;4");
+    }
+
+    #[test]
+    fn test_read_transactions_without_headers() {
+        let input = "dispute,1,9\ndeposit,1,1,5\ndeposit,2,2,7\n";
+        let transaction_reader = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        let records: Vec<Result<Transaction, InputError>> = read_transactions(
+            "batch.csv".to_string(),
+            transaction_reader,
+            [Some(0), Some(1), Some(2), Some(3), Some(4), Some(5)],
+            b',',
+        )
+        .collect();
+
+        // Rows are not held to the field count of the first row
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.as_ref().unwrap().transaction_amount)
+                .collect::<Vec<_>>(),
+            [None, Some(dec!(5)), Some(dec!(7))]
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::Engine,
+    jsonl::{read_json_lines, JsonLinesFile},
+    store::AccountStore,
+    types::{errors::ApplicationError, Transaction, TransactionOutcome},
+};
+
+/// Single entry of the transaction journal
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub transaction: Transaction,
+}
+
+/// Append-only write-ahead journal of accepted transactions.
+///
+/// Every transaction applied by the engine is written to the journal, with a sequence number,
+/// before it changes account state. Replaying the journal in order rebuilds the account state.
+#[derive(Debug)]
+pub struct Journal {
+    file: JsonLinesFile,
+    next_sequence: u64,
+}
+
+impl Journal {
+    /// Open the journal at the given path, creating it if it does not exist. New entries continue
+    /// the sequence of the existing ones.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ApplicationError> {
+        let path: &Path = path.as_ref();
+        let mut next_sequence: u64 = 1;
+        let mut valid_len: u64 = 0;
+        if path.exists() {
+            valid_len = read_journal(path, |entry| {
+                next_sequence = entry.sequence + 1;
+                Ok(())
+            })?;
+        }
+
+        Ok(Journal {
+            file: JsonLinesFile::open(path, valid_len)?,
+            next_sequence,
+        })
+    }
+
+    /// Append a transaction to the journal, returning its sequence number
+    pub fn append(&mut self, transaction: &Transaction) -> Result<u64, ApplicationError> {
+        let entry = JournalEntry {
+            sequence: self.next_sequence,
+            transaction: transaction.clone(),
+        };
+        self.file.append(&entry)?;
+        self.next_sequence += 1;
+
+        Ok(entry.sequence)
+    }
+
+    /// Remove the last appended entry, for a transaction which failed after it was journaled. Its
+    /// sequence number is given to the next entry.
+    pub fn remove_last(&mut self) -> Result<(), ApplicationError> {
+        self.file.remove_last()?;
+        self.next_sequence -= 1;
+
+        Ok(())
+    }
+
+    /// Whether the journal has no entries
+    pub fn is_empty(&self) -> bool {
+        self.next_sequence == 1
+    }
+}
+
+// Read journal entries in order, checking the sequence numbers are contiguous
+fn read_journal<F>(path: &Path, mut apply: F) -> Result<u64, ApplicationError>
+where
+    F: FnMut(JournalEntry) -> Result<(), ApplicationError>,
+{
+    let mut expected_sequence: u64 = 1;
+    read_json_lines(path, |entry: JournalEntry| {
+        if entry.sequence != expected_sequence {
+            return Err(ApplicationError::InvalidData(format!(
+                "Journal [{:?}] - Expected sequence [{}] but found [{}]",
+                path, expected_sequence, entry.sequence
+            )));
+        }
+        expected_sequence += 1;
+        apply(entry)
+    })
+}
+
+/// Rebuild account state by applying every transaction of the journal to the engine, in order.
+/// Returns the number of transactions replayed.
+pub fn replay<S: AccountStore>(
+    journal_file_str: &str,
+    engine: &mut Engine<S>,
+) -> Result<u64, ApplicationError> {
+    let mut replayed: u64 = 0;
+    read_journal(Path::new(journal_file_str), |entry| {
+        match engine.process_transaction(&entry.transaction) {
+            Ok(TransactionOutcome::Applied) => {
+                replayed += 1;
+                Ok(())
+            }
+            Ok(outcome) => Err(ApplicationError::InvalidData(format!(
+                "Journal [{}] - Entry [{}] could not be replayed - {:?}",
+                journal_file_str, entry.sequence, outcome
+            ))),
+            Err(err) => Err(ApplicationError::InvalidData(format!(
+                "Journal [{}] - Entry [{}] could not be replayed - {}",
+                journal_file_str, entry.sequence, err
+            ))),
+        }
+    })?;
+
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use rust_decimal_macros::dec;
+
+    use crate::{
+        engine::Engine,
+        journal::{replay, Journal},
+        types::{Transaction, TransactionType},
+    };
+
+    #[test]
+    fn test_journal_replay() {
+        let journal_path = env::temp_dir().join(format!(
+            "transaction_engine_journal_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&journal_path);
+
+        let transactions = [
+            (TransactionType::DEPOSIT, 1, Some(dec!(20.0))),
+            (TransactionType::WITHDRAWAL, 2, Some(dec!(30.0))),
+            (TransactionType::DEPOSIT, 3, Some(dec!(5.5))),
+            (TransactionType::DISPUTE, 3, None),
+        ];
+        {
+            let mut engine = Engine::new().with_journal(Journal::open(&journal_path).unwrap());
+            for (transaction_type, transaction_id, transaction_amount) in transactions {
+                let _ = engine.process_transaction(&Transaction {
+                    client_id: 1,
+                    transaction_id,
+                    transaction_type,
+                    transaction_amount,
+                    reason: None,
+                    reason_code: None,
+                });
+            }
+        }
+
+        // The rejected withdrawal is not journaled, numbering continues after reopening
+        let mut journal = Journal::open(&journal_path).unwrap();
+        let sequence = journal
+            .append(&Transaction {
+                client_id: 2,
+                transaction_id: 4,
+                transaction_type: TransactionType::DEPOSIT,
+                transaction_amount: Some(dec!(1.0)),
+                reason: None,
+                reason_code: None,
+            })
+            .unwrap();
+        assert_eq!(sequence, 4);
+
+        let mut engine = Engine::new();
+        assert_eq!(
+            replay(journal_path.to_str().unwrap(), &mut engine).unwrap(),
+            4
+        );
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.available, dec!(20.0));
+        assert_eq!(account.held, dec!(5.5));
+        assert_eq!(engine.account(2).unwrap().available, dec!(1.0));
+
+        fs::remove_file(&journal_path).unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::types::errors::ApplicationError;
+
+/// Append-only file of JSON records, one record per line.
+///
+/// Each record is synced to disk as it is written. A record only partially written when the
+/// process stops is incomplete, and is discarded when the file is read back.
+#[derive(Debug)]
+pub(crate) struct JsonLinesFile {
+    path: PathBuf,
+    file: File,
+    file_len: u64,
+    // Length of the file before the last record was appended
+    previous_len: u64,
+}
+
+impl JsonLinesFile {
+    /// Open a file for appending, creating it if it does not exist. `valid_len` is the length of
+    /// the complete records in the file, anything after it is truncated.
+    pub(crate) fn open(path: &Path, valid_len: u64) -> Result<Self, ApplicationError> {
+        let file_error =
+            |err: std::io::Error| ApplicationError::io(format!("Unable to open [{:?}]", path), err);
+
+        let file: File = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(file_error)?;
+        if file.metadata().map_err(file_error)?.len() > valid_len {
+            file.set_len(valid_len).map_err(file_error)?;
+        }
+
+        Ok(JsonLinesFile {
+            path: path.to_path_buf(),
+            file,
+            file_len: valid_len,
+            previous_len: valid_len,
+        })
+    }
+
+    /// Write a record as a single line and sync it to disk. A partially written line is truncated
+    /// so later records are never appended to an incomplete one.
+    pub(crate) fn append<T: Serialize>(&mut self, record: &T) -> Result<(), ApplicationError> {
+        let mut line: Vec<u8> = serde_json::to_vec(record).map_err(|err| {
+            ApplicationError::json(
+                format!("Error serializing record for [{:?}]", self.path),
+                err,
+            )
+        })?;
+        line.push(b'\n');
+
+        let write_result = self
+            .file
+            .write_all(&line)
+            .and_then(|_| self.file.sync_data());
+        if let Err(err) = write_result {
+            let _ = self.file.set_len(self.file_len);
+            return Err(ApplicationError::io(
+                format!("Error writing to [{:?}]", self.path),
+                err,
+            ));
+        }
+        self.previous_len = self.file_len;
+        self.file_len += line.len() as u64;
+
+        Ok(())
+    }
+
+    /// Remove the record written by the last `append`, e.g. when the change it records was
+    /// abandoned. Only the last record can be removed.
+    pub(crate) fn remove_last(&mut self) -> Result<(), ApplicationError> {
+        self.file
+            .set_len(self.previous_len)
+            .and_then(|_| self.file.sync_data())
+            .map_err(|err| {
+                ApplicationError::io(format!("Error truncating [{:?}]", self.path), err)
+            })?;
+        self.file_len = self.previous_len;
+
+        Ok(())
+    }
+}
+
+/// Read every complete record of a JSON lines file in order, returning the length in bytes of the
+/// complete records. Only the final record can be incomplete, an unreadable record followed by
+/// further records is reported as corruption.
+pub(crate) fn read_json_lines<T, F>(path: &Path, mut apply: F) -> Result<u64, ApplicationError>
+where
+    T: DeserializeOwned,
+    F: FnMut(T) -> Result<(), ApplicationError>,
+{
+    let file: File = File::open(path)
+        .map_err(|err| ApplicationError::io(format!("Unable to open [{:?}]", path), err))?;
+    let mut reader = BufReader::new(file);
+
+    let mut valid_len: u64 = 0;
+    let mut line_number: usize = 0;
+    let mut incomplete_line: Option<usize> = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let bytes_read: usize = reader
+            .read_line(&mut line)
+            .map_err(|err| ApplicationError::io(format!("Error reading [{:?}]", path), err))?;
+        if bytes_read == 0 {
+            break;
+        }
+        line_number += 1;
+
+        if let Some(incomplete_line_number) = incomplete_line {
+            return Err(ApplicationError::InvalidData(format!(
+                "File [{:?}] is corrupt at line {}",
+                path, incomplete_line_number
+            )));
+        }
+        if !line.ends_with('\n') {
+            incomplete_line = Some(line_number);
+            continue;
+        }
+        match serde_json::from_str::<T>(&line) {
+            Ok(record) => {
+                apply(record)?;
+                valid_len += bytes_read as u64;
+            }
+            Err(err) => {
+                debug!("Unreadable record in [{:?}] - [{:?}]", path, err);
+                incomplete_line = Some(line_number);
+            }
+        }
+    }
+    if let Some(line_number) = incomplete_line {
+        warn!(
+            "[{:?}] - Discarding incomplete record at line {}",
+            path, line_number
+        );
+    }
+
+    Ok(valid_len)
+}
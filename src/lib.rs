@@ -1,2 +1,10 @@
+pub mod budget;
 pub mod engine;
+pub mod input;
+pub mod journal;
+mod jsonl;
+pub mod output;
+pub mod parallel;
+pub mod snapshot;
+pub mod store;
 pub mod types;
@@ -1,13 +1,17 @@
 #![allow(clippy::upper_case_acronyms)]
 
-use std::env;
+use std::{env, error::Error, process::ExitCode};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use log::debug;
-use types::errors::ApplicationError;
-
-mod engine;
-mod types;
+use serde::Serialize;
+use transaction_engine::{
+    budget::ErrorBudget,
+    engine::{self, BatchConfig, DisputePolicy},
+    input::{ColumnMapping, CsvLayout, InputFormat},
+    output::{OutputFormat, SortKey, SummaryOutput},
+    types::errors::ApplicationError,
+};
 
 const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
@@ -17,25 +21,262 @@ const APP_NAME: &str = env!("CARGO_PKG_NAME");
     name = APP_NAME,
     version = APP_VERSION,
     about = "Engine/Tool to process transaction data",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
 )]
 struct CLI {
-    /// Path of input file in CSV format
-    transaction_file_path: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Paths of input files in CSV format, or directories of CSV files, processed in order into
+    /// one account summary. `-` reads from stdin
+    #[arg(required = true)]
+    transaction_file_paths: Vec<String>,
+
+    /// Optional format of the input files, `csv` or `ndjson`, defaults to `csv`
+    #[arg(long)]
+    input_format: Option<InputFormat>,
+
+    /// Input files have no header, columns are identified by position
+    #[arg(long)]
+    no_headers: bool,
+
+    /// Optional field delimiter of the input files, defaults to a comma
+    #[arg(long, value_parser = parse_delimiter)]
+    delimiter: Option<u8>,
+
+    /// Optional columns holding each transaction field, e.g. `client=client_id,tx=txn_id`.
+    /// Columns are numbered from 1 for input without headers
+    #[arg(long)]
+    column_mapping: Option<ColumnMapping>,
+
+    /// Optional path of output file for rejected and ignored transactions, in CSV format
+    #[arg(long)]
+    rejections_file_path: Option<String>,
+
+    /// Optional path of output file for the lock and unlock operator actions applied, with the
+    /// operator's reason, in CSV format
+    #[arg(long)]
+    audit_file_path: Option<String>,
+
+    /// Abort on the first input record which cannot be read as a transaction, reporting its file,
+    /// line and column. By default such records are skipped
+    #[arg(long)]
+    strict: bool,
+
+    /// Optional path of output file for input records which cannot be read as a transaction, in
+    /// CSV format
+    #[arg(long, conflicts_with = "strict")]
+    quarantine_file_path: Option<String>,
+
+    /// Optional maximum number of malformed records and rejected transactions, the batch is
+    /// rejected without an account summary once exceeded
+    #[arg(long)]
+    max_errors: Option<u64>,
+
+    /// Optional maximum fraction of input records which are malformed or rejected, from 0 to 1.
+    /// Checked once the input is processed, the batch is rejected without an account summary if
+    /// exceeded
+    #[arg(long, value_parser = parse_error_rate)]
+    max_error_rate: Option<f64>,
+
+    /// Optional maximum size of each input file in megabytes, larger files are rejected
+    #[arg(long)]
+    max_file_size_mb: Option<u64>,
+
+    /// Optional path of a persistent account store, account state is carried over between runs
+    #[arg(long)]
+    store_path: Option<String>,
+
+    /// Optional path of an account snapshot to restore before processing, in JSON format
+    #[arg(long)]
+    load_snapshot: Option<String>,
+
+    /// Optional path to save an account snapshot to after processing, in JSON format
+    #[arg(long)]
+    save_snapshot: Option<String>,
+
+    /// Optional path of output file for the dispute history of every client, in CSV format
+    #[arg(long)]
+    dispute_history_path: Option<String>,
+
+    /// Optional path of a journal file, every applied transaction is appended to it
+    #[arg(long)]
+    journal_path: Option<String>,
+
+    /// Optional number of worker threads, transactions are sharded across workers by client
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    workers: Option<u16>,
+
+    /// Optional path of output file for the account summary, written to stdout if not set
+    #[arg(long, global = true)]
+    output: Option<String>,
+
+    /// Optional format of the account summary, `csv`, `json` or `ndjson`, defaults to `csv`
+    #[arg(long, global = true)]
+    output_format: Option<OutputFormat>,
+
+    /// Optional order of the account summary, `client`, `available`, `held` or `total`, defaults
+    /// to `client`. Accounts with equal values are ordered by client
+    #[arg(long, global = true)]
+    sort_by: Option<SortKey>,
+
+    /// Optional original transactions which can be disputed, `deposits` or
+    /// `deposits-and-withdrawals`, defaults to `deposits`. A disputed withdrawal holds its amount
+    /// and returns it to the available funds on a chargeback
+    #[arg(long, global = true)]
+    dispute_policy: Option<DisputePolicy>,
+
+    /// Optional format of errors written to stderr, `text` or `json`, defaults to `text`
+    #[arg(long, global = true, value_parser = ["text", "json"])]
+    error_format: Option<String>,
 
     /// Optional log level
-    #[arg(long, value_parser = ["error", "warn", "info", "debug", "trace"])]
+    #[arg(long, global = true, value_parser = ["error", "warn", "info", "debug", "trace"])]
     log_level: Option<String>,
 }
 
-fn main() -> Result<(), ApplicationError> {
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Rebuild account state from a journal file and output the account summary
+    Replay {
+        /// Path of the journal file
+        journal_file_path: String,
+    },
+}
+
+// Error written to stderr with `--error-format json`
+#[derive(Serialize)]
+struct ErrorReport<'a> {
+    error: &'static str,
+    exit_code: u8,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<u64>,
+    /// Messages of the underlying errors, outermost first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    causes: Vec<String>,
+}
+
+impl<'a> From<&'a ApplicationError> for ErrorReport<'a> {
+    fn from(err: &'a ApplicationError) -> Self {
+        let (file, line, column) = match err {
+            ApplicationError::MalformedRecord {
+                file, line, column, ..
+            } => (Some(file.as_str()), Some(*line), *column),
+            _ => (None, None, None),
+        };
+        let mut causes: Vec<String> = Vec::new();
+        let mut source: Option<&dyn Error> = err.source();
+        while let Some(cause) = source {
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+
+        ErrorReport {
+            error: err.category(),
+            exit_code: err.exit_code(),
+            message: err.to_string(),
+            file,
+            line,
+            column,
+            causes,
+        }
+    }
+}
+
+fn main() -> ExitCode {
     let cli = CLI::parse();
     debug!("CLI Arguments provided: {:?}", cli);
-    if let Some(level) = cli.log_level {
+    if let Some(level) = &cli.log_level {
         env::set_var("RUST_LOG", level);
     }
     env_logger::init();
 
-    engine::run_transactions(cli.transaction_file_path.as_str())?;
+    let json_errors: bool = cli.error_format.as_deref() == Some("json");
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            match json_errors {
+                true => eprintln!(
+                    "{}",
+                    serde_json::to_string(&ErrorReport::from(&err))
+                        .unwrap_or_else(|_| err.to_string())
+                ),
+                false => eprintln!("Error: {} - {}", err.category(), err.report()),
+            }
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+fn run(cli: CLI) -> Result<(), ApplicationError> {
+    let summary_output = SummaryOutput {
+        path: cli.output,
+        format: cli.output_format.unwrap_or_default(),
+        sort_key: cli.sort_by.unwrap_or_default(),
+    };
+
+    if let Some(Command::Replay { journal_file_path }) = cli.command {
+        return engine::replay_transactions(
+            &journal_file_path,
+            cli.dispute_policy.unwrap_or_default(),
+            &summary_output,
+        );
+    }
+
+    engine::run_transactions(&BatchConfig {
+        transaction_file_paths: cli.transaction_file_paths,
+        input_format: cli.input_format.unwrap_or_default(),
+        csv_layout: CsvLayout {
+            has_headers: !cli.no_headers,
+            delimiter: cli.delimiter.unwrap_or(b','),
+            column_mapping: cli.column_mapping.unwrap_or_default(),
+        },
+        summary_output,
+        rejections_file_path: cli.rejections_file_path,
+        audit_file_path: cli.audit_file_path,
+        strict: cli.strict,
+        quarantine_file_path: cli.quarantine_file_path,
+        error_budget: ErrorBudget {
+            max_errors: cli.max_errors,
+            max_error_rate: cli.max_error_rate,
+        },
+        max_file_size_mb: cli.max_file_size_mb,
+        store_path: cli.store_path,
+        load_snapshot_path: cli.load_snapshot,
+        save_snapshot_path: cli.save_snapshot,
+        journal_path: cli.journal_path,
+        workers: cli.workers.map(usize::from),
+        dispute_policy: cli.dispute_policy.unwrap_or_default(),
+        dispute_history_path: cli.dispute_history_path,
+    })?;
 
     Ok(())
 }
+
+// Delimiters are a single ASCII character
+fn parse_delimiter(delimiter_str: &str) -> Result<u8, String> {
+    match delimiter_str.as_bytes() {
+        [delimiter] if delimiter.is_ascii() => Ok(*delimiter),
+        _ => Err(format!(
+            "Delimiter [{}] must be a single ASCII character",
+            delimiter_str
+        )),
+    }
+}
+
+// Error rates are a fraction of the input records
+fn parse_error_rate(rate_str: &str) -> Result<f64, String> {
+    match rate_str.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!(
+            "Error rate [{}] must be a number between 0 and 1",
+            rate_str
+        )),
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use csv::Writer;
+use log::debug;
+
+use crate::types::{errors::ApplicationError, AccountView, DisputeView};
+
+/// Format of the account summary
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// CSV with a header row
+    #[default]
+    Csv,
+    /// A single JSON array of accounts
+    Json,
+    /// One JSON object per account, one account per line
+    Ndjson,
+}
+
+impl FromStr for OutputFormat {
+    type Err = ApplicationError;
+
+    fn from_str(format_str: &str) -> Result<Self, Self::Err> {
+        match format_str {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            _ => Err(ApplicationError::InvalidData(format!(
+                "Output format [{}] is not one of csv, json, ndjson",
+                format_str
+            ))),
+        }
+    }
+}
+
+/// Order of accounts in the summary. Accounts with equal values are ordered by client id, so the
+/// summary of the same account state is always identical.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SortKey {
+    #[default]
+    Client,
+    Available,
+    Held,
+    Total,
+}
+
+impl FromStr for SortKey {
+    type Err = ApplicationError;
+
+    fn from_str(key_str: &str) -> Result<Self, Self::Err> {
+        match key_str {
+            "client" => Ok(SortKey::Client),
+            "available" => Ok(SortKey::Available),
+            "held" => Ok(SortKey::Held),
+            "total" => Ok(SortKey::Total),
+            _ => Err(ApplicationError::InvalidData(format!(
+                "Sort key [{}] is not one of client, available, held, total",
+                key_str
+            ))),
+        }
+    }
+}
+
+/// Where and how the account summary is written
+#[derive(Clone, Debug, Default)]
+pub struct SummaryOutput {
+    /// Path of the output file, the summary is written to stdout if not set
+    pub path: Option<String>,
+    pub format: OutputFormat,
+    pub sort_key: SortKey,
+}
+
+/// Write account views to the writer in the given format and order. Amounts are written with four
+/// decimal places in every format.
+pub(crate) fn write_accounts<W, I>(
+    writer: W,
+    output_format: OutputFormat,
+    sort_key: SortKey,
+    accounts: I,
+) -> Result<(), ApplicationError>
+where
+    W: Write,
+    I: Iterator<Item = AccountView>,
+{
+    let mut accounts: Vec<AccountView> = accounts.collect();
+    accounts.sort_by(|first, second| {
+        let ordering = match sort_key {
+            SortKey::Client => std::cmp::Ordering::Equal,
+            SortKey::Available => first.available.cmp(&second.available),
+            SortKey::Held => first.held.cmp(&second.held),
+            SortKey::Total => first.total.cmp(&second.total),
+        };
+        ordering.then(first.client_id.cmp(&second.client_id))
+    });
+    let accounts = accounts.into_iter();
+
+    match output_format {
+        OutputFormat::Csv => write_csv_accounts(writer, accounts),
+        OutputFormat::Json | OutputFormat::Ndjson => {
+            write_json_accounts(writer, output_format == OutputFormat::Json, accounts)
+        }
+    }
+}
+
+fn write_csv_accounts<W, I>(writer: W, accounts: I) -> Result<(), ApplicationError>
+where
+    W: Write,
+    I: Iterator<Item = AccountView>,
+{
+    let mut csv_writer = Writer::from_writer(writer);
+    for account_view in accounts {
+        debug!("Serialized Account View: \n{:?}", account_view);
+        csv_writer.serialize(&account_view).map_err(|err| {
+            ApplicationError::csv(
+                format!(
+                    "Error serializing account status [{:?}]",
+                    account_view.client_id
+                ),
+                err,
+            )
+        })?;
+    }
+
+    csv_writer
+        .flush()
+        .map_err(|err| ApplicationError::io("Error serializing CSV data", err))?;
+
+    Ok(())
+}
+
+// Accounts are written either as the elements of a single array, or as one object per line
+fn write_json_accounts<W, I>(writer: W, as_array: bool, accounts: I) -> Result<(), ApplicationError>
+where
+    W: Write,
+    I: Iterator<Item = AccountView>,
+{
+    let write_error =
+        |err: std::io::Error| ApplicationError::io("Error writing account summary", err);
+    let mut json_writer = BufWriter::new(writer);
+    let (opening, separator, closing): (&[u8], &[u8], &[u8]) = match as_array {
+        true => (b"[", b",", b"]\n"),
+        false => (b"", b"", b""),
+    };
+
+    json_writer.write_all(opening).map_err(write_error)?;
+    for (index, account_view) in accounts.enumerate() {
+        debug!("Serialized Account View: \n{:?}", account_view);
+        if index > 0 {
+            json_writer.write_all(separator).map_err(write_error)?;
+        }
+        serde_json::to_writer(&mut json_writer, &account_view).map_err(|err| {
+            ApplicationError::json(
+                format!(
+                    "Error serializing account status [{:?}]",
+                    account_view.client_id
+                ),
+                err,
+            )
+        })?;
+        if !as_array {
+            json_writer.write_all(b"\n").map_err(write_error)?;
+        }
+    }
+    json_writer.write_all(closing).map_err(write_error)?;
+    json_writer.flush().map_err(write_error)?;
+
+    Ok(())
+}
+
+/// Write dispute views to the writer in CSV format, ordered by client and by the order the disputes
+/// of each client were opened
+pub(crate) fn write_disputes<W, I>(writer: W, disputes: I) -> Result<(), ApplicationError>
+where
+    W: Write,
+    I: Iterator<Item = DisputeView>,
+{
+    let mut disputes: Vec<DisputeView> = disputes.collect();
+    disputes.sort_by_key(|dispute_view| (dispute_view.client_id, dispute_view.sequence));
+
+    let mut csv_writer = Writer::from_writer(writer);
+    for dispute_view in disputes {
+        csv_writer.serialize(&dispute_view).map_err(|err| {
+            ApplicationError::csv(
+                format!(
+                    "Error serializing dispute [{:?}]",
+                    dispute_view.transaction_id
+                ),
+                err,
+            )
+        })?;
+    }
+
+    csv_writer
+        .flush()
+        .map_err(|err| ApplicationError::io("Error serializing CSV data", err))?;
+
+    Ok(())
+}
+
+/// Write a file through `write`, replacing any existing file only once it is completely written.
+/// The content is written to a temporary file alongside the destination, synced to disk and
+/// renamed into place.
+pub(crate) fn write_file_atomically<F>(file_str: &str, write: F) -> Result<(), ApplicationError>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), ApplicationError>,
+{
+    let temporary_path = PathBuf::from(format!("{}.tmp", file_str));
+    let file_error = |err: std::io::Error| {
+        ApplicationError::io(format!("Error writing file [{}]", file_str), err)
+    };
+
+    let mut writer = BufWriter::new(File::create(&temporary_path).map_err(file_error)?);
+    let write_result = write(&mut writer).and_then(|_| {
+        let file: File = writer
+            .into_inner()
+            .map_err(|err| file_error(err.into_error()))?;
+        file.sync_all().map_err(file_error)
+    });
+    if let Err(err) = write_result {
+        let _ = fs::remove_file(&temporary_path);
+        return Err(err);
+    }
+    fs::rename(&temporary_path, file_str).map_err(file_error)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use rust_decimal_macros::dec;
+
+    use crate::{
+        output::{write_accounts, OutputFormat, SortKey},
+        types::{errors::ApplicationError, AccountView},
+    };
+
+    // Writer which fails every write, as when the output device is full
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("device full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn account_views() -> Vec<AccountView> {
+        vec![
+            AccountView {
+                client_id: 1,
+                available: dec!(1.5),
+                held: dec!(0.25),
+                locked: false,
+                total: dec!(1.75),
+            },
+            AccountView {
+                client_id: 2,
+                available: dec!(0.0),
+                held: dec!(0.0),
+                locked: true,
+                total: dec!(0.0),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_write_accounts_formats() {
+        let mut output: Vec<u8> = Vec::new();
+        write_accounts(
+            &mut output,
+            OutputFormat::Json,
+            SortKey::Client,
+            account_views().into_iter(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.2500\",\"locked\":false,\"total\":\"1.7500\"},\
+            {\"client\":2,\"available\":\"0.0000\",\"held\":\"0.0000\",\"locked\":true,\"total\":\"0.0000\"}]\n"
+        );
+
+        let mut output: Vec<u8> = Vec::new();
+        write_accounts(
+            &mut output,
+            OutputFormat::Ndjson,
+            SortKey::Client,
+            account_views().into_iter(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.2500\",\"locked\":false,\"total\":\"1.7500\"}\n\
+            {\"client\":2,\"available\":\"0.0000\",\"held\":\"0.0000\",\"locked\":true,\"total\":\"0.0000\"}\n"
+        );
+
+        let mut output: Vec<u8> = Vec::new();
+        write_accounts(
+            &mut output,
+            OutputFormat::Json,
+            SortKey::Client,
+            Vec::new().into_iter(),
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "[]\n");
+
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_write_accounts_reports_write_failure() {
+        for output_format in [OutputFormat::Csv, OutputFormat::Json] {
+            let err = write_accounts(
+                FailingWriter,
+                output_format,
+                SortKey::Client,
+                account_views().into_iter(),
+            )
+            .unwrap_err();
+            assert_eq!(err.category(), "FileAccess");
+            assert!(err.report().ends_with(" - device full"), "{}", err.report());
+        }
+
+        // CSV errors of the underlying writer are reported as file access errors
+        let err = ApplicationError::csv(
+            "Error writing rejection record",
+            csv::Error::from(io::Error::other("device full")),
+        );
+        assert_eq!((err.category(), err.exit_code()), ("FileAccess", 10));
+        assert_eq!(err.report(), "Error writing rejection record - device full");
+    }
+
+    #[test]
+    fn test_write_accounts_sorted() {
+        let mut account_views = account_views();
+        account_views.push(AccountView {
+            client_id: 0,
+            available: dec!(0.0),
+            held: dec!(0.0),
+            locked: false,
+            total: dec!(0.0),
+        });
+        account_views.reverse();
+
+        let mut output: Vec<u8> = Vec::new();
+        write_accounts(
+            &mut output,
+            OutputFormat::Csv,
+            SortKey::Client,
+            account_views.clone().into_iter(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,locked,total\n\
+            0,0.0000,0.0000,false,0.0000\n\
+            1,1.5000,0.2500,false,1.7500\n\
+            2,0.0000,0.0000,true,0.0000\n"
+        );
+
+        // Equal totals fall back to client id order
+        let mut output: Vec<u8> = Vec::new();
+        write_accounts(
+            &mut output,
+            OutputFormat::Csv,
+            SortKey::Total,
+            account_views.into_iter(),
+        )
+        .unwrap();
+        let clients: Vec<String> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().to_string())
+            .collect();
+        assert_eq!(clients, vec!["0", "2", "1"]);
+    }
+}
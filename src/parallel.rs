@@ -0,0 +1,441 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use log::debug;
+
+use crate::{
+    engine::{
+        duplicate_transaction_error, transaction_ownership_error, unknown_transaction_error,
+        DisputePolicy, Engine,
+    },
+    store::InMemoryAccountStore,
+    types::{
+        errors::ApplicationError, ClientIdentifier, Transaction, TransactionIdentifier,
+        TransactionOutcome,
+    },
+};
+
+/// Number of transactions queued per worker before reading more input blocks
+const WORKER_QUEUE_SIZE: usize = 1024;
+
+// Transaction ids of the deposits and withdrawals dispatched to workers, shared between the
+// dispatcher and the workers. An id belongs to a client once a worker has applied a transaction
+// with it, and is released again if every transaction dispatched with it failed.
+#[derive(Default)]
+struct TransactionClaims {
+    state: Mutex<ClaimsState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct ClaimsState {
+    claims: HashMap<TransactionIdentifier, Claim>,
+    // Set once a worker has stopped, the claims of its transactions are never resolved
+    worker_stopped: bool,
+}
+
+struct Claim {
+    client_id: ClientIdentifier,
+    // Transactions dispatched with the id which the worker has not applied yet
+    pending: usize,
+    applied: bool,
+}
+
+impl TransactionClaims {
+    fn lock(&self) -> Result<MutexGuard<'_, ClaimsState>, ApplicationError> {
+        self.state.lock().map_err(|_| {
+            ApplicationError::WorkerFailure("Transaction claims are unusable".to_string())
+        })
+    }
+
+    // Check a transaction against the claimed ids before it is dispatched, returning the reason
+    // to reject it if any. A transaction of another client which claimed the same id is waited on
+    // until its worker has applied it or failed, so ids are only known once applied, exactly as
+    // in sequential processing. The worker of the client checks its own claims.
+    fn check(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Option<ApplicationError>, ApplicationError> {
+        let transaction_id: TransactionIdentifier = transaction.transaction_id;
+        let mut state = self.lock()?;
+        loop {
+            if state.worker_stopped {
+                return Err(ApplicationError::WorkerFailure(
+                    "Transaction worker stopped".to_string(),
+                ));
+            }
+            match state.claims.get(&transaction_id) {
+                Some(claim) if claim.client_id != transaction.client_id && !claim.applied => {
+                    state = self.changed.wait(state).map_err(|_| {
+                        ApplicationError::WorkerFailure(
+                            "Transaction claims are unusable".to_string(),
+                        )
+                    })?;
+                }
+                _ => break,
+            }
+        }
+
+        let transaction_type = transaction.transaction_type;
+        let rejection: Option<ApplicationError> = match state.claims.get_mut(&transaction_id) {
+            Some(claim) if transaction_type.is_originating() && claim.applied => {
+                Some(duplicate_transaction_error(transaction))
+            }
+            Some(claim) if transaction_type.is_originating() => {
+                claim.pending += 1;
+                None
+            }
+            None if transaction_type.is_originating() => {
+                state.claims.insert(
+                    transaction_id,
+                    Claim {
+                        client_id: transaction.client_id,
+                        pending: 1,
+                        applied: false,
+                    },
+                );
+                None
+            }
+            Some(claim)
+                if transaction_type.is_referencing()
+                    && claim.client_id != transaction.client_id =>
+            {
+                Some(transaction_ownership_error(transaction, claim.client_id))
+            }
+            None if transaction_type.is_referencing() => {
+                Some(unknown_transaction_error(transaction))
+            }
+            _ => None,
+        };
+
+        Ok(rejection)
+    }
+
+    // Record the outcome of a dispatched deposit or withdrawal
+    fn resolve(&self, transaction: &Transaction, applied: bool) {
+        let Ok(mut state) = self.lock() else {
+            return;
+        };
+        if let Some(claim) = state.claims.get_mut(&transaction.transaction_id) {
+            claim.pending = claim.pending.saturating_sub(1);
+            claim.applied |= applied;
+            if claim.pending == 0 && !claim.applied {
+                state.claims.remove(&transaction.transaction_id);
+            }
+        }
+        self.changed.notify_all();
+    }
+}
+
+// Marks the claims as no longer resolved when a worker stops, including when it panics, so the
+// dispatcher never waits on it
+struct WorkerStopGuard<'a>(&'a TransactionClaims);
+
+impl Drop for WorkerStopGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.0.state.lock() {
+            state.worker_stopped = true;
+        }
+        self.0.changed.notify_all();
+    }
+}
+
+/// Apply transactions using a pool of worker threads, each owning the accounts of a subset of
+/// clients, and merge the account state of all workers into a single engine once the input is
+/// exhausted.
+///
+/// Transactions are routed to workers by client id, so the transactions of a client are applied
+/// in input order. `on_outcome` is called with the outcome of every transaction from a separate
+/// thread, in the order workers complete them. Processing stops once `on_outcome` fails, e.g. when
+/// the error budget is exceeded, and its error is returned.
+///
+/// Transaction ids are only taken by deposits and withdrawals which are applied, as in sequential
+/// processing. A deposit or withdrawal reusing the id of an applied one is rejected as a duplicate,
+/// and disputes, resolves and chargebacks are rejected when they reference an unknown transaction
+/// or the transaction of another client. A transaction sharing its id with a transaction of
+/// another client which is still being applied waits for its outcome.
+pub fn process_sharded<I, F>(
+    transactions: I,
+    workers: usize,
+    dispute_policy: DisputePolicy,
+    mut on_outcome: F,
+) -> Result<Engine, ApplicationError>
+where
+    I: Iterator<Item = Transaction>,
+    F: FnMut(&Transaction, &TransactionOutcome) -> Result<(), ApplicationError> + Send,
+{
+    let workers: usize = workers.max(1);
+    let claims = TransactionClaims::default();
+    let outcome_failed = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let (outcome_sender, outcome_receiver) =
+            mpsc::channel::<(Transaction, TransactionOutcome)>();
+        let outcome_failed: &AtomicBool = &outcome_failed;
+        let outcome_handle = scope.spawn(move || -> Result<(), ApplicationError> {
+            for (transaction, outcome) in outcome_receiver {
+                if let Err(err) = on_outcome(&transaction, &outcome) {
+                    outcome_failed.store(true, Ordering::Relaxed);
+                    return Err(err);
+                }
+            }
+            Ok(())
+        });
+
+        let mut transaction_senders = Vec::with_capacity(workers);
+        let mut worker_handles = Vec::with_capacity(workers);
+        for worker in 0..workers {
+            let (transaction_sender, transaction_receiver) =
+                mpsc::sync_channel::<Transaction>(WORKER_QUEUE_SIZE);
+            let outcome_sender = outcome_sender.clone();
+            let claims: &TransactionClaims = &claims;
+            worker_handles.push(scope.spawn(move || {
+                let _stop_guard = WorkerStopGuard(claims);
+                let mut engine = Engine::new().with_dispute_policy(dispute_policy);
+                for transaction in transaction_receiver {
+                    let outcome: TransactionOutcome = engine
+                        .process_transaction(&transaction)
+                        .unwrap_or_else(|err| TransactionOutcome::Rejected { reason: err });
+                    if transaction.transaction_type.is_originating() {
+                        claims
+                            .resolve(&transaction, matches!(outcome, TransactionOutcome::Applied));
+                    }
+                    // The outcome thread only stops early once an outcome failed
+                    if outcome_sender.send((transaction, outcome)).is_err() {
+                        break;
+                    }
+                }
+                debug!("Worker [{}] finished", worker);
+                engine.into_store()
+            }));
+            transaction_senders.push(transaction_sender);
+        }
+
+        for transaction in transactions {
+            // The failed outcome is reported when the outcome thread is joined
+            if outcome_failed.load(Ordering::Relaxed) {
+                break;
+            }
+            // A stopped worker is reported when the workers are joined
+            let Ok(rejection) = claims.check(&transaction) else {
+                break;
+            };
+            if let Some(reason) = rejection {
+                let outcome = TransactionOutcome::Rejected { reason };
+                let _ = outcome_sender.send((transaction, outcome));
+                continue;
+            }
+
+            // Client ids are evenly distributed, so they are used as their own hash
+            let worker: usize = transaction.client_id as usize % workers;
+            if transaction_senders[worker].send(transaction).is_err() {
+                break;
+            }
+        }
+        drop(transaction_senders);
+        drop(outcome_sender);
+
+        let mut store = InMemoryAccountStore::new();
+        for worker_handle in worker_handles {
+            let worker_store: InMemoryAccountStore = worker_handle.join().map_err(|_| {
+                ApplicationError::WorkerFailure("Transaction worker thread panicked".to_string())
+            })?;
+            store.merge(worker_store);
+        }
+        outcome_handle.join().map_err(|_| {
+            ApplicationError::WorkerFailure("Transaction outcome thread panicked".to_string())
+        })??;
+
+        Ok(Engine::with_store(store))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rust_decimal_macros::dec;
+
+    use crate::{
+        engine::DisputePolicy,
+        parallel::process_sharded,
+        types::{errors::ApplicationError, Transaction, TransactionOutcome, TransactionType},
+    };
+
+    #[test]
+    fn test_sharded_processing_matches_sequential_order() {
+        let mut transactions: Vec<Transaction> = Vec::new();
+        for client_id in 1..=8 {
+            let base_id: u32 = client_id as u32 * 10;
+            transactions.push(Transaction {
+                client_id,
+                transaction_id: base_id,
+                transaction_type: TransactionType::DEPOSIT,
+                transaction_amount: Some(dec!(50.0)),
+                reason: None,
+                reason_code: None,
+            });
+            // Refused when applied in input order, before the second deposit
+            transactions.push(Transaction {
+                client_id,
+                transaction_id: base_id + 1,
+                transaction_type: TransactionType::WITHDRAWAL,
+                transaction_amount: Some(dec!(60.0)),
+                reason: None,
+                reason_code: None,
+            });
+            transactions.push(Transaction {
+                client_id,
+                transaction_id: base_id + 2,
+                transaction_type: TransactionType::DEPOSIT,
+                transaction_amount: Some(dec!(20.0)),
+                reason: None,
+                reason_code: None,
+            });
+        }
+        // Duplicate id across clients
+        transactions.push(Transaction {
+            client_id: 2,
+            transaction_id: 10,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(1.0)),
+            reason: None,
+            reason_code: None,
+        });
+        // Dispute of another client's deposit, and of an unknown transaction
+        for transaction_id in [10, 99] {
+            transactions.push(Transaction {
+                client_id: 2,
+                transaction_id,
+                transaction_type: TransactionType::DISPUTE,
+                transaction_amount: None,
+                reason: None,
+                reason_code: None,
+            });
+        }
+
+        let mut duplicates: usize = 0;
+        let mut insufficient_funds: usize = 0;
+        let mut invalid_references: usize = 0;
+        let engine = process_sharded(
+            transactions.into_iter(),
+            3,
+            DisputePolicy::default(),
+            |_, outcome| {
+                match outcome {
+                    TransactionOutcome::Rejected {
+                        reason: ApplicationError::DuplicateTransaction(_),
+                    } => duplicates += 1,
+                    TransactionOutcome::Rejected {
+                        reason: ApplicationError::InsufficientFunds(_),
+                    } => insufficient_funds += 1,
+                    TransactionOutcome::Rejected {
+                        reason:
+                            ApplicationError::TransactionOwnership(_)
+                            | ApplicationError::UnknownTransaction(_),
+                    } => invalid_references += 1,
+                    _ => {}
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(duplicates, 1);
+        assert_eq!(insufficient_funds, 8);
+        assert_eq!(invalid_references, 2);
+        for client_id in 1..=8 {
+            assert_eq!(engine.account(client_id).unwrap().available, dec!(70.0));
+        }
+    }
+
+    #[test]
+    fn test_sharded_processing_stops_on_failed_outcome() {
+        const TRANSACTION_COUNT: usize = 100_000;
+        let read_transactions = AtomicUsize::new(0);
+        let transactions = (0..TRANSACTION_COUNT)
+            .map(|index| Transaction {
+                client_id: (index % 4) as u16,
+                transaction_id: index as u32,
+                transaction_type: TransactionType::WITHDRAWAL,
+                transaction_amount: Some(dec!(1.0)),
+                reason: None,
+                reason_code: None,
+            })
+            .inspect(|_| {
+                read_transactions.fetch_add(1, Ordering::Relaxed);
+            });
+
+        // Every withdrawal is refused, the first refusal fails the batch
+        let result = process_sharded(
+            transactions,
+            4,
+            DisputePolicy::DepositsOnly,
+            |_, outcome| match outcome {
+                TransactionOutcome::Rejected { reason } => Err(reason.clone()),
+                _ => Ok(()),
+            },
+        );
+        assert!(matches!(
+            result,
+            Err(ApplicationError::InsufficientFunds(_))
+        ));
+        assert!(read_transactions.load(Ordering::Relaxed) < TRANSACTION_COUNT);
+    }
+
+    #[test]
+    fn test_sharded_processing_only_claims_applied_ids() {
+        let transaction =
+            |transaction_type, client_id, transaction_id, transaction_amount| Transaction {
+                client_id,
+                transaction_id,
+                transaction_type,
+                transaction_amount,
+                reason: None,
+                reason_code: None,
+            };
+        let transactions = vec![
+            transaction(TransactionType::DEPOSIT, 1, 1, Some(dec!(10.0))),
+            // Refused, the id is taken by the deposit which follows
+            transaction(TransactionType::WITHDRAWAL, 2, 5, Some(dec!(3.0))),
+            transaction(TransactionType::DISPUTE, 1, 5, None),
+            transaction(TransactionType::DEPOSIT, 2, 5, Some(dec!(7.0))),
+            transaction(TransactionType::DEPOSIT, 3, 5, Some(dec!(1.0))),
+            transaction(TransactionType::DISPUTE, 3, 5, None),
+        ];
+
+        let mut rejections: Vec<(u16, &'static str)> = Vec::new();
+        let engine = process_sharded(
+            transactions.into_iter(),
+            2,
+            DisputePolicy::default(),
+            |transaction, outcome| {
+                if let TransactionOutcome::Rejected { reason } = outcome {
+                    rejections.push((transaction.client_id, reason.category()));
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        rejections.sort();
+        assert_eq!(
+            rejections,
+            [
+                (1, "UnknownTransaction"),
+                (2, "InsufficientFunds"),
+                (3, "DuplicateTransaction"),
+                (3, "TransactionOwnership"),
+            ]
+        );
+        assert_eq!(engine.account(1).unwrap().available, dec!(10.0));
+        assert_eq!(engine.account(2).unwrap().available, dec!(7.0));
+        assert!(engine.account(3).is_none());
+    }
+}
@@ -0,0 +1,136 @@
+use std::{fs::File, io::BufReader};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    output::write_file_atomically,
+    store::AccountStore,
+    types::{errors::ApplicationError, Account, TransactionIdentifier},
+};
+
+// Full account state at the end of a batch, including the transaction logs of every account and
+// the transaction ids processed so far, in JSON format.
+#[derive(Debug, Deserialize, Serialize)]
+struct Snapshot<A> {
+    accounts: Vec<A>,
+    processed_transaction_ids: Vec<TransactionIdentifier>,
+}
+
+/// Load account state from a snapshot file into the store, replacing any accounts already present
+/// for the same clients
+pub fn load_snapshot<S: AccountStore>(
+    snapshot_file_str: &str,
+    store: &mut S,
+) -> Result<(), ApplicationError> {
+    let snapshot_file: File = File::open(snapshot_file_str).map_err(|err| {
+        ApplicationError::io(
+            format!("Unable to open snapshot file [{}]", snapshot_file_str),
+            err,
+        )
+    })?;
+    let snapshot: Snapshot<Account> = serde_json::from_reader(BufReader::new(snapshot_file))
+        .map_err(|err| {
+            ApplicationError::json(
+                format!("Invalid snapshot file [{}]", snapshot_file_str),
+                err,
+            )
+        })?;
+
+    store.insert_transaction_ids(&snapshot.processed_transaction_ids)?;
+    for account in snapshot.accounts {
+        store.put(account, None)?;
+    }
+
+    Ok(())
+}
+
+/// Save the account state held in the store to a snapshot file. The snapshot is written to a
+/// temporary file first and moved into place, so an existing snapshot is never left half written.
+pub fn save_snapshot<S: AccountStore>(
+    snapshot_file_str: &str,
+    store: &S,
+) -> Result<(), ApplicationError> {
+    let snapshot: Snapshot<&Account> = Snapshot {
+        accounts: store.accounts().collect(),
+        processed_transaction_ids: store.transaction_ids().collect(),
+    };
+
+    write_file_atomically(snapshot_file_str, |writer| {
+        serde_json::to_writer(writer, &snapshot).map_err(|err| {
+            ApplicationError::json(
+                format!("Error serializing snapshot [{}]", snapshot_file_str),
+                err,
+            )
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use rust_decimal_macros::dec;
+
+    use crate::{
+        snapshot::{load_snapshot, save_snapshot},
+        store::{AccountStore, InMemoryAccountStore},
+        types::{Account, Transaction, TransactionType},
+    };
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let snapshot_path = env::temp_dir().join(format!(
+            "transaction_engine_snapshot_{}.json",
+            std::process::id()
+        ));
+        let snapshot_file_str = snapshot_path.to_str().unwrap();
+
+        let deposit = Transaction {
+            client_id: 3,
+            transaction_id: 11,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(2.75)),
+            reason: None,
+            reason_code: None,
+        };
+        let mut account = Account::new(3);
+        account.held = dec!(2.75);
+        account
+            .disputed_transactions_log
+            .insert(11, deposit.clone());
+
+        let mut store = InMemoryAccountStore::new();
+        store.put(account, Some(11)).unwrap();
+        save_snapshot(snapshot_file_str, &store).unwrap();
+
+        let mut restored_store = InMemoryAccountStore::new();
+        load_snapshot(snapshot_file_str, &mut restored_store).unwrap();
+        assert_eq!(restored_store.get(3).unwrap().held, dec!(2.75));
+        assert_eq!(restored_store.disputed_transaction(3, 11), Some(&deposit));
+        assert!(restored_store.contains_transaction_id(11));
+
+        fs::remove_file(&snapshot_path).unwrap();
+    }
+
+    #[test]
+    fn test_load_invalid_snapshot() {
+        let snapshot_path = env::temp_dir().join(format!(
+            "transaction_engine_invalid_snapshot_{}.json",
+            std::process::id()
+        ));
+        let snapshot_file_str = snapshot_path.to_str().unwrap();
+        fs::write(&snapshot_path, "{\"accounts\": 1}").unwrap();
+
+        let err = load_snapshot(snapshot_file_str, &mut InMemoryAccountStore::new()).unwrap_err();
+        assert_eq!(err.category(), "InvalidData");
+        assert_eq!(
+            err.report(),
+            format!(
+                "Invalid snapshot file [{}] - invalid type: integer `1`, expected a sequence at line 1 column 15",
+                snapshot_file_str
+            )
+        );
+
+        fs::remove_file(&snapshot_path).unwrap();
+    }
+}
@@ -0,0 +1,708 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    jsonl::{read_json_lines, JsonLinesFile},
+    types::{
+        errors::ApplicationError, Account, ClientIdentifier, Dispute, Transaction,
+        TransactionIdentifier, ValueAmount,
+    },
+};
+
+/// Storage backend for account state used by the engine.
+///
+/// A store holds every client account along with the settled and disputed transaction logs and the
+/// disputes of each account, the set of transaction ids processed across all clients, and an index of the
+/// client each transaction belongs to.
+pub trait AccountStore {
+    /// Look up the account of a client
+    fn get(&self, client_id: ClientIdentifier) -> Option<&Account>;
+
+    /// Account of a client for updating in place, created empty if the client has no account.
+    ///
+    /// Changes made through the returned reference are held in memory until they are committed.
+    fn entry(&mut self, client_id: ClientIdentifier) -> &mut Account;
+
+    /// Persist the changes made through `entry` by a single transaction. Only the balances of the
+    /// account and the log entries and dispute of `transaction_id` may have changed.
+    ///
+    /// When `processed` is set the transaction id is recorded as processed as part of the same
+    /// update.
+    fn commit(
+        &mut self,
+        client_id: ClientIdentifier,
+        transaction_id: TransactionIdentifier,
+        processed: bool,
+    ) -> Result<(), ApplicationError>;
+
+    /// Drop an account created by `entry` which was never committed
+    fn discard(&mut self, client_id: ClientIdentifier);
+
+    /// Insert or replace the account of a client.
+    ///
+    /// `processed_transaction_id` is recorded as part of the same update, so that account state
+    /// and the set of processed transaction ids never diverge.
+    fn put(
+        &mut self,
+        account: Account,
+        processed_transaction_id: Option<TransactionIdentifier>,
+    ) -> Result<(), ApplicationError>;
+
+    /// Iterate over all accounts in the store
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_>;
+
+    /// Whether a transaction id has already been processed, for any client
+    fn contains_transaction_id(&self, transaction_id: TransactionIdentifier) -> bool;
+
+    /// Client a processed transaction belongs to, `None` if the transaction is unknown. The
+    /// client is known for every transaction committed, held in a transaction log or disputed,
+    /// but not for transaction ids only restored through `insert_transaction_ids`.
+    fn transaction_owner(&self, transaction_id: TransactionIdentifier) -> Option<ClientIdentifier>;
+
+    /// Iterate over all processed transaction ids
+    fn transaction_ids(&self) -> Box<dyn Iterator<Item = TransactionIdentifier> + '_>;
+
+    /// Record transaction ids as processed, e.g. when restoring state from a snapshot
+    fn insert_transaction_ids(
+        &mut self,
+        transaction_ids: &[TransactionIdentifier],
+    ) -> Result<(), ApplicationError>;
+
+    /// Look up a settled transaction of a client
+    fn settled_transaction(
+        &self,
+        client_id: ClientIdentifier,
+        transaction_id: TransactionIdentifier,
+    ) -> Option<&Transaction> {
+        self.get(client_id)?
+            .settled_transactions_log
+            .get(&transaction_id)
+    }
+
+    /// Look up a disputed transaction of a client
+    fn disputed_transaction(
+        &self,
+        client_id: ClientIdentifier,
+        transaction_id: TransactionIdentifier,
+    ) -> Option<&Transaction> {
+        self.get(client_id)?
+            .disputed_transactions_log
+            .get(&transaction_id)
+    }
+}
+
+/// Default store, keeping all account state in memory for the lifetime of the engine
+#[derive(Debug, Default)]
+pub struct InMemoryAccountStore {
+    account_data: HashMap<ClientIdentifier, Account>,
+    transaction_ids: HashSet<TransactionIdentifier>,
+    transaction_owners: HashMap<TransactionIdentifier, ClientIdentifier>,
+}
+
+impl InMemoryAccountStore {
+    pub fn new() -> Self {
+        InMemoryAccountStore::default()
+    }
+
+    /// Move all accounts and processed transaction ids of another store into this one. Accounts
+    /// of the other store replace accounts of the same clients.
+    pub fn merge(&mut self, other: InMemoryAccountStore) {
+        self.account_data.extend(other.account_data);
+        self.transaction_ids.extend(other.transaction_ids);
+        self.transaction_owners.extend(other.transaction_owners);
+    }
+
+    // Index the transactions held in the logs of an account, or disputed, as belonging to its
+    // client. A transaction fully charged back is only left in the disputes of the account.
+    fn index_account_transactions(&mut self, account: &Account) {
+        for transaction_id in account
+            .settled_transactions_log
+            .keys()
+            .chain(account.disputed_transactions_log.keys())
+            .chain(account.disputes.keys())
+        {
+            self.transaction_owners
+                .insert(*transaction_id, account.client_id);
+        }
+    }
+}
+
+impl AccountStore for InMemoryAccountStore {
+    fn get(&self, client_id: ClientIdentifier) -> Option<&Account> {
+        self.account_data.get(&client_id)
+    }
+
+    fn entry(&mut self, client_id: ClientIdentifier) -> &mut Account {
+        self.account_data
+            .entry(client_id)
+            .or_insert_with(|| Account::new(client_id))
+    }
+
+    fn commit(
+        &mut self,
+        client_id: ClientIdentifier,
+        transaction_id: TransactionIdentifier,
+        processed: bool,
+    ) -> Result<(), ApplicationError> {
+        if processed {
+            self.transaction_ids.insert(transaction_id);
+            self.transaction_owners.insert(transaction_id, client_id);
+        }
+
+        Ok(())
+    }
+
+    fn discard(&mut self, client_id: ClientIdentifier) {
+        self.account_data.remove(&client_id);
+    }
+
+    fn put(
+        &mut self,
+        account: Account,
+        processed_transaction_id: Option<TransactionIdentifier>,
+    ) -> Result<(), ApplicationError> {
+        if let Some(transaction_id) = processed_transaction_id {
+            self.transaction_ids.insert(transaction_id);
+            self.transaction_owners
+                .insert(transaction_id, account.client_id);
+        }
+        self.index_account_transactions(&account);
+        self.account_data.insert(account.client_id, account);
+
+        Ok(())
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        Box::new(self.account_data.values())
+    }
+
+    fn contains_transaction_id(&self, transaction_id: TransactionIdentifier) -> bool {
+        self.transaction_ids.contains(&transaction_id)
+    }
+
+    fn transaction_owner(&self, transaction_id: TransactionIdentifier) -> Option<ClientIdentifier> {
+        self.transaction_owners.get(&transaction_id).copied()
+    }
+
+    fn transaction_ids(&self) -> Box<dyn Iterator<Item = TransactionIdentifier> + '_> {
+        Box::new(self.transaction_ids.iter().copied())
+    }
+
+    fn insert_transaction_ids(
+        &mut self,
+        transaction_ids: &[TransactionIdentifier],
+    ) -> Result<(), ApplicationError> {
+        self.transaction_ids.extend(transaction_ids);
+
+        Ok(())
+    }
+}
+
+/// Persistent store, keeping account state in an append-only file on local disk so that state
+/// carries over between engine runs.
+///
+/// Each update is written as a single line and synced to disk before it is applied in memory. An
+/// update interrupted by a crash is incomplete and discarded the next time the store is opened,
+/// so `available` and `held` always reflect whole transactions. Only the balances and the log
+/// entries of the applied transaction are written per update, and the file is compacted every time
+/// it is opened.
+#[derive(Debug)]
+pub struct FileAccountStore {
+    file: JsonLinesFile,
+    accounts: InMemoryAccountStore,
+}
+
+// Single line of the store file
+#[derive(Debug, Deserialize, Serialize)]
+enum StoreRecord {
+    Account(AccountRecord),
+    ProcessedTransactions(Vec<TransactionIdentifier>),
+    // Client of each transaction id, kept so the owner of a transaction no longer held in any
+    // transaction log survives compaction
+    TransactionOwners(Vec<(TransactionIdentifier, ClientIdentifier)>),
+}
+
+// Account balances along with the transaction log entries inserted or removed, and the disputes
+// inserted or updated, by an update. A record replacing the account clears its transaction logs and
+// disputes before the entries are inserted.
+#[derive(Debug, Deserialize, Serialize)]
+struct AccountRecord {
+    client_id: ClientIdentifier,
+    available: ValueAmount,
+    held: ValueAmount,
+    locked: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    replace: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    settled: Vec<Transaction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    settled_removed: Vec<TransactionIdentifier>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    disputed: Vec<Transaction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    disputed_removed: Vec<TransactionIdentifier>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    disputes: Vec<Dispute>,
+    #[serde(default)]
+    dispute_count: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    processed_transaction_id: Option<TransactionIdentifier>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl AccountRecord {
+    /// Record replacing an account with its full current state
+    fn replacement(
+        account: &Account,
+        processed_transaction_id: Option<TransactionIdentifier>,
+    ) -> Self {
+        AccountRecord {
+            client_id: account.client_id,
+            available: account.available,
+            held: account.held,
+            locked: account.locked,
+            replace: true,
+            settled: account.settled_transactions_log.values().cloned().collect(),
+            settled_removed: Vec::new(),
+            disputed: account
+                .disputed_transactions_log
+                .values()
+                .cloned()
+                .collect(),
+            disputed_removed: Vec::new(),
+            disputes: account.disputes.values().flatten().cloned().collect(),
+            dispute_count: account.dispute_count,
+            processed_transaction_id,
+        }
+    }
+
+    /// Record of the balances, the log entries and the last dispute of a single transaction.
+    /// Entries absent from a log are recorded as removed, whether or not they were present before.
+    /// Disputes are never removed once opened, so only the last one can have changed.
+    fn transaction_changes(
+        account: &Account,
+        transaction_id: TransactionIdentifier,
+        processed_transaction_id: Option<TransactionIdentifier>,
+    ) -> Self {
+        let (settled, settled_removed) =
+            log_entry_change(&account.settled_transactions_log, transaction_id);
+        let (disputed, disputed_removed) =
+            log_entry_change(&account.disputed_transactions_log, transaction_id);
+
+        AccountRecord {
+            client_id: account.client_id,
+            available: account.available,
+            held: account.held,
+            locked: account.locked,
+            replace: false,
+            settled,
+            settled_removed,
+            disputed,
+            disputed_removed,
+            disputes: account
+                .disputes
+                .get(&transaction_id)
+                .and_then(|disputes| disputes.last())
+                .cloned()
+                .into_iter()
+                .collect(),
+            dispute_count: account.dispute_count,
+            processed_transaction_id,
+        }
+    }
+}
+
+fn log_entry_change(
+    log: &HashMap<TransactionIdentifier, Transaction>,
+    transaction_id: TransactionIdentifier,
+) -> (Vec<Transaction>, Vec<TransactionIdentifier>) {
+    match log.get(&transaction_id) {
+        Some(transaction) => (vec![transaction.clone()], Vec::new()),
+        None => (Vec::new(), vec![transaction_id]),
+    }
+}
+
+impl InMemoryAccountStore {
+    // Replay a persisted record on top of the current state
+    fn apply_record(&mut self, record: StoreRecord) {
+        match record {
+            StoreRecord::Account(record) => {
+                let account = self
+                    .account_data
+                    .entry(record.client_id)
+                    .or_insert_with(|| Account::new(record.client_id));
+                if record.replace {
+                    *account = Account::new(record.client_id);
+                }
+                account.available = record.available;
+                account.held = record.held;
+                account.locked = record.locked;
+                account.dispute_count = record.dispute_count;
+                for transaction_id in record.settled_removed {
+                    account.settled_transactions_log.remove(&transaction_id);
+                }
+                for transaction in record.settled {
+                    self.transaction_owners
+                        .insert(transaction.transaction_id, record.client_id);
+                    account
+                        .settled_transactions_log
+                        .insert(transaction.transaction_id, transaction);
+                }
+                for transaction_id in record.disputed_removed {
+                    account.disputed_transactions_log.remove(&transaction_id);
+                }
+                for transaction in record.disputed {
+                    self.transaction_owners
+                        .insert(transaction.transaction_id, record.client_id);
+                    account
+                        .disputed_transactions_log
+                        .insert(transaction.transaction_id, transaction);
+                }
+                // Disputes are updated in place, or added once opened
+                for dispute in record.disputes {
+                    let disputes = account.disputes.entry(dispute.transaction_id).or_default();
+                    match disputes
+                        .iter_mut()
+                        .find(|recorded| recorded.sequence == dispute.sequence)
+                    {
+                        Some(recorded) => *recorded = dispute,
+                        None => disputes.push(dispute),
+                    }
+                }
+                if let Some(transaction_id) = record.processed_transaction_id {
+                    self.transaction_ids.insert(transaction_id);
+                    self.transaction_owners
+                        .insert(transaction_id, record.client_id);
+                }
+            }
+            StoreRecord::ProcessedTransactions(transaction_ids) => {
+                self.transaction_ids.extend(transaction_ids);
+            }
+            StoreRecord::TransactionOwners(transaction_owners) => {
+                self.transaction_owners.extend(transaction_owners);
+            }
+        }
+    }
+}
+
+impl FileAccountStore {
+    /// Open the store at the given path, creating it if it does not exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ApplicationError> {
+        let path: &Path = path.as_ref();
+        let mut accounts = InMemoryAccountStore::new();
+        if path.exists() {
+            read_json_lines(path, |record: StoreRecord| {
+                accounts.apply_record(record);
+                Ok(())
+            })?;
+        }
+
+        let file_len: u64 = compact_store_file(path, &accounts)?;
+
+        Ok(FileAccountStore {
+            file: JsonLinesFile::open(path, file_len)?,
+            accounts,
+        })
+    }
+}
+
+// Rewrite the store file with one record per account, replacing the previous file atomically.
+// Returns the length of the compacted file.
+fn compact_store_file(
+    path: &Path,
+    accounts: &InMemoryAccountStore,
+) -> Result<u64, ApplicationError> {
+    let compacted_path: PathBuf = path.with_extension("compact");
+    let file_error = |err: std::io::Error| {
+        ApplicationError::io(format!("Error compacting account store [{:?}]", path), err)
+    };
+
+    let mut file: File = File::create(&compacted_path).map_err(file_error)?;
+    let mut records: Vec<StoreRecord> = vec![
+        StoreRecord::ProcessedTransactions(accounts.transaction_ids.iter().copied().collect()),
+        StoreRecord::TransactionOwners(
+            accounts
+                .transaction_owners
+                .iter()
+                .map(|(transaction_id, client_id)| (*transaction_id, *client_id))
+                .collect(),
+        ),
+    ];
+    records.extend(
+        accounts
+            .account_data
+            .values()
+            .map(|account| StoreRecord::Account(AccountRecord::replacement(account, None))),
+    );
+    for record in records {
+        serde_json::to_writer(&mut file, &record).map_err(|err| {
+            ApplicationError::json(
+                format!("Error serializing account store record for [{:?}]", path),
+                err,
+            )
+        })?;
+        file.write_all(b"\n").map_err(file_error)?;
+    }
+    file.sync_all().map_err(file_error)?;
+    let file_len: u64 = file.metadata().map_err(file_error)?.len();
+    fs::rename(&compacted_path, path).map_err(file_error)?;
+
+    Ok(file_len)
+}
+
+impl AccountStore for FileAccountStore {
+    fn get(&self, client_id: ClientIdentifier) -> Option<&Account> {
+        self.accounts.get(client_id)
+    }
+
+    fn entry(&mut self, client_id: ClientIdentifier) -> &mut Account {
+        self.accounts.entry(client_id)
+    }
+
+    fn commit(
+        &mut self,
+        client_id: ClientIdentifier,
+        transaction_id: TransactionIdentifier,
+        processed: bool,
+    ) -> Result<(), ApplicationError> {
+        let account: &Account = self.accounts.get(client_id).ok_or_else(|| {
+            ApplicationError::InvalidData(format!(
+                "Transaction id [{}] - No account to commit for client [{}]",
+                transaction_id, client_id
+            ))
+        })?;
+        let record = AccountRecord::transaction_changes(
+            account,
+            transaction_id,
+            processed.then_some(transaction_id),
+        );
+        self.file.append(&StoreRecord::Account(record))?;
+
+        self.accounts.commit(client_id, transaction_id, processed)
+    }
+
+    fn discard(&mut self, client_id: ClientIdentifier) {
+        self.accounts.discard(client_id);
+    }
+
+    fn put(
+        &mut self,
+        account: Account,
+        processed_transaction_id: Option<TransactionIdentifier>,
+    ) -> Result<(), ApplicationError> {
+        let record = AccountRecord::replacement(&account, processed_transaction_id);
+        self.file.append(&StoreRecord::Account(record))?;
+
+        self.accounts.put(account, processed_transaction_id)
+    }
+
+    fn accounts(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        self.accounts.accounts()
+    }
+
+    fn contains_transaction_id(&self, transaction_id: TransactionIdentifier) -> bool {
+        self.accounts.contains_transaction_id(transaction_id)
+    }
+
+    fn transaction_owner(&self, transaction_id: TransactionIdentifier) -> Option<ClientIdentifier> {
+        self.accounts.transaction_owner(transaction_id)
+    }
+
+    fn transaction_ids(&self) -> Box<dyn Iterator<Item = TransactionIdentifier> + '_> {
+        self.accounts.transaction_ids()
+    }
+
+    fn insert_transaction_ids(
+        &mut self,
+        transaction_ids: &[TransactionIdentifier],
+    ) -> Result<(), ApplicationError> {
+        self.file.append(&StoreRecord::ProcessedTransactions(
+            transaction_ids.to_vec(),
+        ))?;
+
+        self.accounts.insert_transaction_ids(transaction_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, env, fs, io::Write, path::PathBuf};
+
+    use rust_decimal_macros::dec;
+
+    use crate::{
+        store::{AccountStore, FileAccountStore, InMemoryAccountStore},
+        types::{
+            errors::ApplicationError, Account, Dispute, DisputeState, Transaction, TransactionType,
+        },
+    };
+
+    fn test_store_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!(
+            "transaction_engine_{}_{}.store",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn test_account() -> Account {
+        let mut account = Account::new(1);
+        account.available = dec!(10.1234567);
+        account.settled_transactions_log.insert(
+            7,
+            Transaction {
+                client_id: 1,
+                transaction_id: 7,
+                transaction_type: TransactionType::DEPOSIT,
+                transaction_amount: Some(dec!(10.1234567)),
+                reason: None,
+                reason_code: None,
+            },
+        );
+        account
+    }
+
+    #[test]
+    fn test_in_memory_store_put_and_lookup() {
+        let mut store = InMemoryAccountStore::new();
+        assert!(store.get(1).is_none());
+
+        let deposit = Transaction {
+            client_id: 1,
+            transaction_id: 7,
+            transaction_type: TransactionType::DEPOSIT,
+            transaction_amount: Some(dec!(10.0)),
+            reason: None,
+            reason_code: None,
+        };
+        let account = Account {
+            client_id: 1,
+            available: dec!(10.0),
+            held: dec!(0.0),
+            locked: false,
+            settled_transactions_log: HashMap::from([(7, deposit.clone())]),
+            disputed_transactions_log: HashMap::new(),
+            disputes: HashMap::new(),
+            dispute_count: 0,
+        };
+        assert!(store.put(account, Some(7)).is_ok());
+
+        assert_eq!(store.get(1).unwrap().available, dec!(10.0));
+        assert_eq!(store.accounts().count(), 1);
+        assert!(store.contains_transaction_id(7));
+        assert!(!store.contains_transaction_id(8));
+        assert_eq!(store.transaction_owner(7), Some(1));
+        assert_eq!(store.transaction_owner(8), None);
+        assert_eq!(store.settled_transaction(1, 7), Some(&deposit));
+        assert!(store.disputed_transaction(1, 7).is_none());
+        assert!(store.settled_transaction(2, 7).is_none());
+    }
+
+    #[test]
+    fn test_file_store_restores_state() {
+        let path = test_store_path("restore");
+        {
+            let mut store = FileAccountStore::open(&path).unwrap();
+            assert!(store.put(test_account(), Some(7)).is_ok());
+
+            // Dispute the deposit in place, moving it between logs
+            let account = store.entry(1);
+            let transaction = account.settled_transactions_log.remove(&7).unwrap();
+            account.disputed_transactions_log.insert(7, transaction);
+            account.disputes.insert(
+                7,
+                vec![Dispute {
+                    transaction_id: 7,
+                    sequence: 1,
+                    state: DisputeState::Open,
+                    reason_code: Some("4837".to_string()),
+                    amount: dec!(10.1234567),
+                    resolved: dec!(0.0),
+                    charged_back: dec!(0.0),
+                }],
+            );
+            account.dispute_count = 1;
+            account.held = account.available;
+            account.available = dec!(0.0);
+            assert!(store.commit(1, 7, false).is_ok());
+
+            // An account never committed is not persisted
+            store.entry(2).available = dec!(1.0);
+            store.discard(2);
+        }
+
+        let store = FileAccountStore::open(&path).unwrap();
+        let account = store.get(1).unwrap();
+        assert_eq!(account.available, dec!(0.0));
+        assert_eq!(account.held, dec!(10.1234567));
+        assert!(account.settled_transactions_log.is_empty());
+        assert_eq!(
+            store.disputed_transaction(1, 7).unwrap().transaction_amount,
+            Some(dec!(10.1234567))
+        );
+        assert_eq!(account.disputes[&7][0].reason_code.as_deref(), Some("4837"));
+        assert_eq!(account.dispute_count, 1);
+        assert!(store.contains_transaction_id(7));
+        assert_eq!(store.transaction_owner(7), Some(1));
+        assert!(store.get(2).is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_store_keeps_transaction_owners() {
+        let path = test_store_path("owners");
+        {
+            let mut store = FileAccountStore::open(&path).unwrap();
+            // Transaction 8 is processed but held in no transaction log, as once charged back
+            assert!(store.put(test_account(), Some(8)).is_ok());
+        }
+
+        // The owner outlives compaction when the store is opened again
+        for _ in 0..2 {
+            let store = FileAccountStore::open(&path).unwrap();
+            assert!(store.contains_transaction_id(8));
+            assert_eq!(store.transaction_owner(8), Some(1));
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_store_discards_incomplete_update() {
+        let path = test_store_path("incomplete");
+        {
+            let mut store = FileAccountStore::open(&path).unwrap();
+            assert!(store.put(test_account(), Some(7)).is_ok());
+        }
+        // Simulate a crash part way through writing an update
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"Account\":{\"client_id\":1,\"avail")
+            .unwrap();
+
+        let store = FileAccountStore::open(&path).unwrap();
+        assert_eq!(store.get(1).unwrap().available, dec!(10.1234567));
+        assert!(store.contains_transaction_id(7));
+
+        // A damaged record followed by further updates is corruption
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"not a record\n{}\n").unwrap();
+        assert!(matches!(
+            FileAccountStore::open(&path),
+            Err(ApplicationError::InvalidData(_))
+        ));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -16,9 +16,50 @@ pub enum TransactionType {
     CHARGEBACK,
     DISPUTE,
     RESOLVE,
+    /// Lapse of a dispute which was neither resolved nor charged back in time, releasing the
+    /// disputed funds as for a resolve
+    EXPIRE,
+    /// Administrative freeze of an account, as for a chargeback
+    LOCK,
+    /// Administrative reinstatement of a locked account
+    UNLOCK,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+impl TransactionType {
+    /// Whether the transaction moves funds under its own unique transaction id, as opposed to
+    /// referencing an earlier transaction
+    pub fn is_originating(&self) -> bool {
+        matches!(self, TransactionType::DEPOSIT | TransactionType::WITHDRAWAL)
+    }
+
+    /// Whether the transaction references an earlier deposit or withdrawal by its transaction id
+    pub fn is_referencing(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::DISPUTE
+                | TransactionType::RESOLVE
+                | TransactionType::CHARGEBACK
+                | TransactionType::EXPIRE
+        )
+    }
+
+    /// Whether the transaction settles part or all of the disputed amount of an earlier
+    /// transaction
+    pub fn is_settling(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::RESOLVE | TransactionType::CHARGEBACK | TransactionType::EXPIRE
+        )
+    }
+
+    /// Whether the transaction is an operator action on the account, rather than a movement of
+    /// funds. Administrative transactions carry the reason for the action.
+    pub fn is_administrative(&self) -> bool {
+        matches!(self, TransactionType::LOCK | TransactionType::UNLOCK)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Transaction {
     #[serde(rename = "type")]
     pub transaction_type: TransactionType,
@@ -26,11 +67,86 @@ pub struct Transaction {
     pub client_id: ClientIdentifier,
     #[serde(rename = "tx")]
     pub transaction_id: TransactionIdentifier,
-    #[serde(rename = "amount", serialize_with = "serialize_value_amount_option")]
+    // Amounts are serialized at full precision so transactions can be persisted and read back
+    #[serde(rename = "amount")]
     pub transaction_amount: Option<ValueAmount>,
+    /// Operator reason for an administrative transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Reason code of a dispute, e.g. the code assigned by the card network
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<String>,
+}
+
+/// Stage of a dispute. A dispute is open from the first dispute of a transaction until its
+/// disputed amount is fully resolved, charged back or expired, and ended disputes are final.
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeState {
+    Open,
+    Resolved,
+    ChargedBack,
+    Expired,
+}
+
+impl DisputeState {
+    /// Whether a dispute in this state may move to the given state. Open disputes stay open while
+    /// part of their amount remains disputed, ended disputes never change.
+    pub fn can_transition_to(&self, next_state: DisputeState) -> bool {
+        matches!(
+            (self, next_state),
+            (
+                DisputeState::Open,
+                DisputeState::Open
+                    | DisputeState::Resolved
+                    | DisputeState::ChargedBack
+                    | DisputeState::Expired
+            )
+        )
+    }
+}
+
+/// Record of a dispute of a transaction, kept by the account after the dispute has ended. Once a
+/// dispute has ended, the undisputed remainder of the transaction can be disputed in a new one.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Dispute {
+    pub transaction_id: TransactionIdentifier,
+    /// Position of the dispute among the disputes of the client, in the order they were opened,
+    /// starting from 1
+    pub sequence: u64,
+    pub state: DisputeState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<String>,
+    /// Amount disputed over the lifetime of the dispute
+    pub amount: ValueAmount,
+    /// Part of the disputed amount released by resolves
+    pub resolved: ValueAmount,
+    /// Part of the disputed amount charged back
+    pub charged_back: ValueAmount,
+}
+
+impl Dispute {
+    /// Part of the disputed amount which is neither resolved nor charged back. Still disputed
+    /// while the dispute is open, released by an expiry once it has ended.
+    pub fn outstanding(&self) -> ValueAmount {
+        self.amount - self.resolved - self.charged_back
+    }
+
+    /// State the dispute ends in once none of its amount remains disputed, from how its amount
+    /// was settled rather than from the transaction settling the last of it. A dispute with any
+    /// part charged back ends charged back, otherwise one with any part expired ends expired.
+    pub fn ended_state(&self) -> DisputeState {
+        if self.charged_back > ValueAmount::ZERO {
+            DisputeState::ChargedBack
+        } else if self.outstanding() > ValueAmount::ZERO {
+            DisputeState::Expired
+        } else {
+            DisputeState::Resolved
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(from = "Account")]
 pub struct AccountView {
     #[serde(rename = "client")]
@@ -48,18 +164,50 @@ pub struct AccountView {
 // The `AccountView` struct is only used when "rendering" an output which will include
 // calculable values. Initial use-case is for the `total` field, which is calculated by
 // adding `available` and `held`.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Account {
     pub client_id: ClientIdentifier,
     pub available: ValueAmount,
     pub held: ValueAmount,
     pub locked: bool,
+    /// Deposits and withdrawals with the part of their amount which is not disputed. A
+    /// transaction leaves the log once its full amount is disputed.
     pub settled_transactions_log: HashMap<TransactionIdentifier, Transaction>,
+    /// Disputed transactions with the part of their amount which is disputed
     pub disputed_transactions_log: HashMap<TransactionIdentifier, Transaction>,
+    /// Disputes of each transaction which has been disputed in the order they were opened, whether
+    /// open or ended. Only the last dispute of a transaction can be open.
+    #[serde(default)]
+    pub disputes: HashMap<TransactionIdentifier, Vec<Dispute>>,
+    /// Number of disputes opened on the account, the sequence of the last dispute opened
+    #[serde(default)]
+    pub dispute_count: u64,
+}
+
+impl Account {
+    /// New account for a client, with no funds and no transaction history
+    pub fn new(client_id: ClientIdentifier) -> Self {
+        Account {
+            client_id,
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            locked: false,
+            settled_transactions_log: HashMap::new(),
+            disputed_transactions_log: HashMap::new(),
+            disputes: HashMap::new(),
+            dispute_count: 0,
+        }
+    }
 }
 
 impl From<Account> for AccountView {
     fn from(value: Account) -> Self {
+        AccountView::from(&value)
+    }
+}
+
+impl From<&Account> for AccountView {
+    fn from(value: &Account) -> Self {
         AccountView {
             client_id: value.client_id,
             available: value.available,
@@ -70,17 +218,298 @@ impl From<Account> for AccountView {
     }
 }
 
+/// Dispute of a client as written to the dispute history, splitting the disputed amount by how it
+/// was settled
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DisputeView {
+    #[serde(rename = "client")]
+    pub client_id: ClientIdentifier,
+    #[serde(rename = "tx")]
+    pub transaction_id: TransactionIdentifier,
+    pub sequence: u64,
+    pub state: DisputeState,
+    pub reason_code: Option<String>,
+    #[serde(serialize_with = "serialize_value_amount")]
+    pub amount: ValueAmount,
+    #[serde(serialize_with = "serialize_value_amount")]
+    pub open: ValueAmount,
+    #[serde(serialize_with = "serialize_value_amount")]
+    pub resolved: ValueAmount,
+    #[serde(serialize_with = "serialize_value_amount")]
+    pub charged_back: ValueAmount,
+    #[serde(serialize_with = "serialize_value_amount")]
+    pub expired: ValueAmount,
+}
+
+impl DisputeView {
+    pub fn new(client_id: ClientIdentifier, dispute: &Dispute) -> Self {
+        // The amount neither resolved nor charged back is still disputed until the dispute ends,
+        // and was released by an expiry once it has ended
+        let (open, expired) = match dispute.state {
+            DisputeState::Open => (dispute.outstanding(), ValueAmount::ZERO),
+            _ => (ValueAmount::ZERO, dispute.outstanding()),
+        };
+        DisputeView {
+            client_id,
+            transaction_id: dispute.transaction_id,
+            sequence: dispute.sequence,
+            state: dispute.state,
+            reason_code: dispute.reason_code.clone(),
+            amount: dispute.amount,
+            open,
+            resolved: dispute.resolved,
+            charged_back: dispute.charged_back,
+            expired,
+        }
+    }
+}
+
+/// Result of processing a single transaction against the account data
+#[derive(Clone, Debug)]
+pub enum TransactionOutcome {
+    /// Transaction was applied to the account
+    Applied,
+    /// Transaction was refused due to a business rule or invalid data
+    Rejected { reason: errors::ApplicationError },
+    /// Transaction had no effect, e.g. referencing a transaction unknown to the account
+    Ignored { reason: String },
+}
+
+// Audit record of a transaction which was not applied, one row of the rejections output.
+#[derive(Debug, Serialize)]
+pub struct TransactionRejection {
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    #[serde(rename = "client")]
+    pub client_id: ClientIdentifier,
+    #[serde(rename = "tx")]
+    pub transaction_id: TransactionIdentifier,
+    #[serde(rename = "amount", serialize_with = "serialize_value_amount_option")]
+    pub transaction_amount: Option<ValueAmount>,
+    pub outcome: &'static str,
+    pub reason: String,
+}
+
+impl TransactionRejection {
+    /// Build a rejection record for a transaction, `None` if the transaction was applied
+    pub fn new(transaction: &Transaction, outcome: &TransactionOutcome) -> Option<Self> {
+        let (outcome, reason) = match outcome {
+            TransactionOutcome::Applied => return None,
+            TransactionOutcome::Rejected { reason } => ("rejected", reason.report()),
+            TransactionOutcome::Ignored { reason } => ("ignored", reason.clone()),
+        };
+        Some(TransactionRejection {
+            transaction_type: transaction.transaction_type,
+            client_id: transaction.client_id,
+            transaction_id: transaction.transaction_id,
+            transaction_amount: transaction.transaction_amount,
+            outcome,
+            reason,
+        })
+    }
+}
+
+// Audit record of an operator action applied to an account with the operator's reason, one row of
+// the audit output.
+#[derive(Debug, Serialize)]
+pub struct OperatorAction {
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    #[serde(rename = "client")]
+    pub client_id: ClientIdentifier,
+    #[serde(rename = "tx")]
+    pub transaction_id: TransactionIdentifier,
+    pub reason: String,
+}
+
+impl OperatorAction {
+    /// Build an audit record for a transaction, `None` unless it is an administrative transaction
+    /// which was applied
+    pub fn new(transaction: &Transaction, outcome: &TransactionOutcome) -> Option<Self> {
+        match outcome {
+            TransactionOutcome::Applied if transaction.transaction_type.is_administrative() => {
+                Some(OperatorAction {
+                    transaction_type: transaction.transaction_type,
+                    client_id: transaction.client_id,
+                    transaction_id: transaction.transaction_id,
+                    reason: transaction.reason.clone().unwrap_or_default(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
 pub mod errors {
-    #[derive(thiserror::Error, Debug, PartialEq)]
+    use std::{error::Error, io, sync::Arc};
+
+    #[derive(thiserror::Error, Clone, Debug)]
     pub enum ApplicationError {
-        #[error("{0}")]
-        FileAccess(String),
+        /// Failed I/O operation, with the underlying error as its source. Reported as a
+        /// `FileAccess` error.
+        #[error("{context}")]
+        Io {
+            context: String,
+            #[source]
+            source: Arc<io::Error>,
+        },
+
+        /// Value which cannot be converted, with the underlying error as its source
+        #[error("{context}")]
+        Conversion {
+            context: String,
+            #[source]
+            source: rust_decimal::Error,
+        },
 
         #[error("{0}")]
         InvalidData(String),
 
+        /// Failed CSV read or write, with the underlying error as its source. Reported as a `CSV`
+        /// error, or as a `FileAccess` error if the underlying reader or writer failed.
+        #[error("{context}")]
+        Csv {
+            context: String,
+            #[source]
+            source: Arc<csv::Error>,
+        },
+
+        /// Failed JSON serialization or deserialization, with the underlying error as its source.
+        /// Reported as an `InvalidData` error.
+        #[error("{context}")]
+        Json {
+            context: String,
+            #[source]
+            source: Arc<serde_json::Error>,
+        },
+
+        #[error("{0}")]
+        AccountLocked(String),
+
+        #[error("{0}")]
+        DuplicateTransaction(String),
+
+        #[error("{0}")]
+        InsufficientFunds(String),
+
+        #[error("{0}")]
+        WorkerFailure(String),
+
+        #[error(
+            "File [{file}] line {line}{} - {message}",
+            .column.map(|column| format!(" column {}", column)).unwrap_or_default()
+        )]
+        MalformedRecord {
+            file: String,
+            line: u64,
+            column: Option<u64>,
+            message: String,
+        },
+
+        #[error("{0}")]
+        ErrorBudgetExceeded(String),
+
+        /// Transaction referencing a transaction id which was never processed
+        #[error("{0}")]
+        UnknownTransaction(String),
+
+        /// Transaction referencing a transaction of a different client
+        #[error("{0}")]
+        TransactionOwnership(String),
+
+        /// Dispute of a transaction which the dispute policy does not permit disputing
+        #[error("{0}")]
+        DisputeNotPermitted(String),
+
+        /// Dispute, resolve, chargeback or expiry of a transaction whose dispute has ended
         #[error("{0}")]
-        CSV(String),
+        DisputeTransition(String),
+    }
+
+    impl ApplicationError {
+        /// Build an I/O error, describing the operation which failed in `context`
+        pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+            ApplicationError::Io {
+                context: context.into(),
+                source: Arc::new(source),
+            }
+        }
+
+        /// Build a CSV error, describing the operation which failed in `context`
+        pub fn csv(context: impl Into<String>, source: csv::Error) -> Self {
+            ApplicationError::Csv {
+                context: context.into(),
+                source: Arc::new(source),
+            }
+        }
+
+        /// Build a JSON error, describing the operation which failed in `context`. A failure of
+        /// the underlying reader or writer is an I/O error.
+        pub fn json(context: impl Into<String>, source: serde_json::Error) -> Self {
+            match source.io_error_kind() {
+                Some(_) => ApplicationError::io(context, io::Error::from(source)),
+                None => ApplicationError::Json {
+                    context: context.into(),
+                    source: Arc::new(source),
+                },
+            }
+        }
+
+        /// Message of the error followed by the message of each of its sources, e.g.
+        /// `Unable to open [data.csv] - No such file or directory (os error 2)`
+        pub fn report(&self) -> String {
+            let mut report: String = self.to_string();
+            let mut source: Option<&dyn Error> = self.source();
+            while let Some(err) = source {
+                report.push_str(&format!(" - {}", err));
+                source = err.source();
+            }
+            report
+        }
+
+        /// Process exit code for the kind of error. Codes are stable across releases so callers
+        /// can branch on the kind of failure, and start from 10 to stay clear of the codes used for
+        /// invalid command line arguments.
+        pub fn exit_code(&self) -> u8 {
+            match self {
+                ApplicationError::Io { .. } => 10,
+                ApplicationError::Csv { source, .. } if source.is_io_error() => 10,
+                ApplicationError::InvalidData(_) | ApplicationError::Json { .. } => 11,
+                ApplicationError::Csv { .. } => 12,
+                ApplicationError::AccountLocked(_) => 13,
+                ApplicationError::DuplicateTransaction(_) => 14,
+                ApplicationError::InsufficientFunds(_) => 15,
+                ApplicationError::WorkerFailure(_) => 16,
+                ApplicationError::MalformedRecord { .. } => 17,
+                ApplicationError::ErrorBudgetExceeded(_) => 18,
+                ApplicationError::Conversion { .. } => 19,
+                ApplicationError::UnknownTransaction(_) => 20,
+                ApplicationError::TransactionOwnership(_) => 21,
+                ApplicationError::DisputeNotPermitted(_) => 22,
+                ApplicationError::DisputeTransition(_) => 23,
+            }
+        }
+
+        /// Name of the kind of error, used to group errors by category
+        pub fn category(&self) -> &'static str {
+            match self {
+                ApplicationError::Io { .. } => "FileAccess",
+                ApplicationError::Csv { source, .. } if source.is_io_error() => "FileAccess",
+                ApplicationError::InvalidData(_) | ApplicationError::Json { .. } => "InvalidData",
+                ApplicationError::Csv { .. } => "CSV",
+                ApplicationError::AccountLocked(_) => "AccountLocked",
+                ApplicationError::DuplicateTransaction(_) => "DuplicateTransaction",
+                ApplicationError::InsufficientFunds(_) => "InsufficientFunds",
+                ApplicationError::WorkerFailure(_) => "WorkerFailure",
+                ApplicationError::MalformedRecord { .. } => "MalformedRecord",
+                ApplicationError::ErrorBudgetExceeded(_) => "ErrorBudgetExceeded",
+                ApplicationError::Conversion { .. } => "Conversion",
+                ApplicationError::UnknownTransaction(_) => "UnknownTransaction",
+                ApplicationError::TransactionOwnership(_) => "TransactionOwnership",
+                ApplicationError::DisputeNotPermitted(_) => "DisputeNotPermitted",
+                ApplicationError::DisputeTransition(_) => "DisputeTransition",
+            }
+        }
     }
 }
 
@@ -1,9 +1,20 @@
 use assert_cmd::Command;
 use chrono::Utc;
 use predicates::prelude::*;
-use std::{error::Error, fs};
+use std::{env, error::Error, fs};
 
-use transaction_engine::engine::MB_THRESHOLD;
+// Path of a file in the temporary directory, so files are not left in the repository when a test
+// fails before removing them
+fn test_path(name: &str) -> String {
+    env::temp_dir()
+        .join(format!(
+            "transaction_engine_cli_{}_{}",
+            name,
+            std::process::id()
+        ))
+        .to_string_lossy()
+        .into_owned()
+}
 
 #[test]
 fn test_basic_transactions() -> Result<(), Box<dyn Error>> {
@@ -22,6 +33,665 @@ fn test_basic_transactions() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn test_rejections_output() -> Result<(), Box<dyn Error>> {
+    let input_file = "data/tests/transaction_batch_010.csv";
+    let now_timestamp = Utc::now().format("%Y%m%d%H%M%S");
+    let rejections_filename = format!("data/tests/integ_test_rejections_{}.csv", now_timestamp);
+
+    Command::cargo_bin("transaction-engine")?
+        .arg(input_file)
+        .arg("--rejections-file-path")
+        .arg(&rejections_filename)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "24,849800.0012,0.0000,false,849800.0012",
+        ));
+
+    let rejections = fs::read_to_string(&rejections_filename)?;
+    assert!(rejections.starts_with("type,client,tx,amount,outcome,reason\n"));
+    assert!(rejections.contains(
+        "resolve,2,2754115120,,rejected,Transaction id [2754115120] - Referenced transaction is unknown"
+    ));
+    assert!(rejections.contains(
+        "chargeback,1,2106385311,,rejected,Transaction id [2106385311] - Referenced transaction is unknown"
+    ));
+    assert!(rejections.contains("withdrawal,2,938335051,918087.9921,rejected,"));
+    assert!(!rejections.contains("deposit,24,2309354759"));
+
+    fs::remove_file(&rejections_filename)?;
+    Ok(())
+}
+
+#[test]
+fn test_persistent_store_across_batches() -> Result<(), Box<dyn Error>> {
+    let now_timestamp = Utc::now().format("%Y%m%d%H%M%S");
+    let store_filename = format!("data/tests/integ_test_store_{}.jsonl", now_timestamp);
+    let first_batch_filename = format!("data/tests/integ_test_store_day1_{}.csv", now_timestamp);
+    let second_batch_filename = format!("data/tests/integ_test_store_day2_{}.csv", now_timestamp);
+
+    fs::write(
+        &first_batch_filename,
+        "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,5.0\n",
+    )?;
+    fs::write(
+        &second_batch_filename,
+        "type,client,tx,amount\ndispute,1,1,\ndeposit,1,2,5.0\n",
+    )?;
+
+    Command::cargo_bin("transaction-engine")?
+        .arg(&first_batch_filename)
+        .arg("--store-path")
+        .arg(&store_filename)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,15.0000,0.0000,false,15.0000"));
+
+    // Yesterday's deposit can be disputed and its id is not reusable
+    Command::cargo_bin("transaction-engine")?
+        .arg(&second_batch_filename)
+        .arg("--store-path")
+        .arg(&store_filename)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,5.0000,10.0000,false,15.0000"));
+
+    fs::remove_file(&store_filename)?;
+    fs::remove_file(&first_batch_filename)?;
+    fs::remove_file(&second_batch_filename)?;
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_chaining() -> Result<(), Box<dyn Error>> {
+    let now_timestamp = Utc::now().format("%Y%m%d%H%M%S");
+    let snapshot_filename = format!("data/tests/integ_test_snapshot_{}.json", now_timestamp);
+    let second_batch_filename =
+        format!("data/tests/integ_test_snapshot_day2_{}.csv", now_timestamp);
+
+    Command::cargo_bin("transaction-engine")?
+        .arg("data/tests/transaction_batch_single_account.csv")
+        .arg("--save-snapshot")
+        .arg(&snapshot_filename)
+        .assert()
+        .success();
+
+    // Dispute the deposit of the previous batch
+    fs::write(
+        &second_batch_filename,
+        "type,client,tx,amount\ndispute,5,1257502121,\n",
+    )?;
+    Command::cargo_bin("transaction-engine")?
+        .arg(&second_batch_filename)
+        .arg("--load-snapshot")
+        .arg(&snapshot_filename)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "5,0.0000,435930.1231,false,435930.1231",
+        ));
+
+    fs::remove_file(&snapshot_filename)?;
+    fs::remove_file(&second_batch_filename)?;
+    Ok(())
+}
+
+#[test]
+fn test_journal_replay() -> Result<(), Box<dyn Error>> {
+    let now_timestamp = Utc::now().format("%Y%m%d%H%M%S");
+    let journal_filename = format!("data/tests/integ_test_journal_{}.jsonl", now_timestamp);
+    let expected_output = "5,435930.1231,0.0000,false,435930.1231";
+
+    Command::cargo_bin("transaction-engine")?
+        .arg("data/tests/transaction_batch_single_account.csv")
+        .arg("--journal-path")
+        .arg(&journal_filename)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(expected_output));
+
+    let journal = fs::read_to_string(&journal_filename)?;
+    assert_eq!(journal.lines().count(), 1);
+    assert!(journal.starts_with("{\"sequence\":1,"));
+
+    Command::cargo_bin("transaction-engine")?
+        .arg("replay")
+        .arg(&journal_filename)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(expected_output));
+
+    fs::remove_file(&journal_filename)?;
+    Ok(())
+}
+
+#[test]
+fn test_journal_requires_empty_opening_state() -> Result<(), Box<dyn Error>> {
+    let (store_filename, snapshot_filename, journal_filename) = (
+        test_path("opening_store.jsonl"),
+        test_path("opening_snapshot.json"),
+        test_path("opening_journal.jsonl"),
+    );
+
+    Command::cargo_bin("transaction-engine")?
+        .arg("-")
+        .arg("--store-path")
+        .arg(&store_filename)
+        .arg("--save-snapshot")
+        .arg(&snapshot_filename)
+        .write_stdin("type,client,tx,amount\ndeposit,1,1,10.0\n")
+        .assert()
+        .success();
+
+    // Replaying the journal would not know of the deposit held in the store or snapshot
+    for (opening_state_arg, opening_state_filename) in [
+        ("--store-path", &store_filename),
+        ("--load-snapshot", &snapshot_filename),
+    ] {
+        Command::cargo_bin("transaction-engine")?
+            .arg("-")
+            .arg(opening_state_arg)
+            .arg(opening_state_filename)
+            .arg("--journal-path")
+            .arg(&journal_filename)
+            .write_stdin("type,client,tx,amount\ndispute,1,1,\n")
+            .assert()
+            .code(11)
+            .stdout("");
+    }
+
+    for filename in [store_filename, snapshot_filename, journal_filename] {
+        let _ = fs::remove_file(filename);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parallel_workers_match_sequential() -> Result<(), Box<dyn Error>> {
+    let input_file = "data/tests/transaction_batch_010.csv";
+
+    let sequential_output = Command::cargo_bin("transaction-engine")?
+        .arg(input_file)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let parallel_output = Command::cargo_bin("transaction-engine")?
+        .arg(input_file)
+        .arg("--workers")
+        .arg("4")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    // Accounts are ordered by client, so both summaries are identical
+    assert_eq!(sequential_output, parallel_output);
+
+    Ok(())
+}
+
+#[test]
+fn test_parallel_workers_only_claim_applied_ids() -> Result<(), Box<dyn Error>> {
+    // The refused withdrawal does not take the id of the deposit which follows it
+    let input_data = "type,client,tx,amount\n\
+        deposit,1,1,10\n\
+        withdrawal,2,5,3\n\
+        deposit,2,5,7\n";
+    let expected_output = "client,available,held,locked,total\n\
+        1,10.0000,0.0000,false,10.0000\n\
+        2,7.0000,0.0000,false,7.0000\n";
+
+    for workers in ["1", "2"] {
+        Command::cargo_bin("transaction-engine")?
+            .arg("-")
+            .arg("--workers")
+            .arg(workers)
+            .write_stdin(input_data)
+            .assert()
+            .success()
+            .stdout(expected_output);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_stdin_input() -> Result<(), Box<dyn Error>> {
+    let input_data = fs::read_to_string("data/tests/transaction_batch_single_account.csv")?;
+
+    Command::cargo_bin("transaction-engine")?
+        .arg("-")
+        .write_stdin(input_data)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "5,435930.1231,0.0000,false,435930.1231",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn test_unlock_account() -> Result<(), Box<dyn Error>> {
+    let input_data = "type,client,tx,amount,reason\n\
+        deposit,1,1,10.0,\n\
+        deposit,1,2,5.0,\n\
+        dispute,1,1,,\n\
+        chargeback,1,1,,\n\
+        deposit,1,3,1.0,\n\
+        unlock,1,4,,\"Chargeback reversed, ticket 1234\"\n\
+        deposit,1,5,2.0,\n\
+        lock,2,6,,Court order\n";
+    let rejections_filename = test_path("unlock_rejections.csv");
+    let audit_filename = test_path("unlock_audit.csv");
+
+    // Client 2 has no account to lock
+    Command::cargo_bin("transaction-engine")?
+        .arg("-")
+        .arg("--rejections-file-path")
+        .arg(&rejections_filename)
+        .arg("--audit-file-path")
+        .arg(&audit_filename)
+        .write_stdin(input_data)
+        .assert()
+        .success()
+        .stdout("client,available,held,locked,total\n1,7.0000,0.0000,false,7.0000\n");
+
+    let rejections = fs::read_to_string(&rejections_filename)?;
+    fs::remove_file(&rejections_filename)?;
+    assert_eq!(
+        rejections,
+        "type,client,tx,amount,outcome,reason\n\
+        deposit,1,3,1.0000,rejected,Transaction id [3] - Account [1] is locked\n\
+        lock,2,6,,rejected,Transaction id [6] - Account [2] does not exist\n"
+    );
+
+    // Applied operator actions are recorded with their reason
+    let audit = fs::read_to_string(&audit_filename)?;
+    fs::remove_file(&audit_filename)?;
+    assert_eq!(
+        audit,
+        "type,client,tx,reason\nunlock,1,4,\"Chargeback reversed, ticket 1234\"\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_dispute_policy() -> Result<(), Box<dyn Error>> {
+    let input_data = "type,client,tx,amount\n\
+        deposit,1,1,10.0\n\
+        withdrawal,1,2,4.0\n\
+        dispute,1,2,\n";
+
+    Command::cargo_bin("transaction-engine")?
+        .arg("-")
+        .write_stdin(input_data)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,6.0000,0.0000,false,6.0000"));
+
+    Command::cargo_bin("transaction-engine")?
+        .arg("-")
+        .arg("--dispute-policy")
+        .arg("deposits-and-withdrawals")
+        .write_stdin(input_data)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,6.0000,4.0000,false,10.0000"));
+
+    Ok(())
+}
+
+#[test]
+fn test_partial_dispute() -> Result<(), Box<dyn Error>> {
+    let input_data = "type,client,tx,amount\n\
+        deposit,1,1,10.0\n\
+        dispute,1,1,4.0\n\
+        dispute,1,1,7.0\n\
+        chargeback,1,1,1.5\n";
+
+    Command::cargo_bin("transaction-engine")?
+        .arg("-")
+        .write_stdin(input_data)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,6.0000,2.5000,true,8.5000"));
+
+    // The rest of the dispute can still be settled once the account is locked
+    Command::cargo_bin("transaction-engine")?
+        .arg("-")
+        .write_stdin(format!("{}resolve,1,1,\ndeposit,1,2,1.0\n", input_data))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,8.5000,0.0000,true,8.5000"));
+
+    Ok(())
+}
+
+#[test]
+fn test_dispute_history() -> Result<(), Box<dyn Error>> {
+    let now_timestamp = Utc::now().format("%Y%m%d%H%M%S");
+    let dispute_history_filename = format!(
+        "data/tests/integ_test_dispute_history_{}.csv",
+        now_timestamp
+    );
+    let input_data = "type,client,tx,amount,reason_code\n\
+        deposit,2,1,10.0,\n\
+        deposit,1,2,8.0,\n\
+        deposit,1,3,5.0,\n\
+        dispute,1,3,,4837\n\
+        dispute,1,2,2.0,\n\
+        resolve,1,3,,\n\
+        dispute,1,3,,\n\
+        dispute,2,1,,4853\n\
+        expire,2,1,,\n\
+        deposit,3,4,100.0,\n\
+        dispute,3,4,60.0,\n\
+        chargeback,3,4,10.0,\n\
+        expire,3,4,,\n";
+
+    Command::cargo_bin("transaction-engine")?
+        .arg("-")
+        .arg("--dispute-history-path")
+        .arg(&dispute_history_filename)
+        .write_stdin(input_data)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "1,6.0000,7.0000,false,13.0000\n2,10.0000,0.0000,false,10.0000\n3,90.0000,0.0000,true,90.0000",
+        ));
+
+    // The resolved dispute is not reopened, disputing the transaction again opens a new dispute.
+    // Disputes are listed per client in the order opened, and nothing is left open once a dispute
+    // has ended.
+    let dispute_history = fs::read_to_string(&dispute_history_filename)?;
+    assert_eq!(
+        dispute_history,
+        "client,tx,sequence,state,reason_code,amount,open,resolved,charged_back,expired\n\
+        1,3,1,resolved,4837,5.0000,0.0000,5.0000,0.0000,0.0000\n\
+        1,2,2,open,,2.0000,2.0000,0.0000,0.0000,0.0000\n\
+        1,3,3,open,,5.0000,5.0000,0.0000,0.0000,0.0000\n\
+        2,1,1,expired,4853,10.0000,0.0000,0.0000,0.0000,10.0000\n\
+        3,4,1,charged_back,,60.0000,0.0000,0.0000,10.0000,50.0000\n"
+    );
+
+    fs::remove_file(&dispute_history_filename)?;
+    Ok(())
+}
+
+#[test]
+fn test_multiple_inputs_combined() -> Result<(), Box<dyn Error>> {
+    let now_timestamp = Utc::now().format("%Y%m%d%H%M%S");
+    let input_directory = format!("data/tests/integ_test_inputs_{}", now_timestamp);
+    let extra_input_filename = format!("data/tests/integ_test_inputs_extra_{}.csv", now_timestamp);
+
+    fs::create_dir(&input_directory)?;
+    fs::write(
+        format!("{}/day1.csv", input_directory),
+        "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,3.0\n",
+    )?;
+    // Processed after day1, the deposit it disputes is already settled
+    fs::write(
+        format!("{}/day2.csv", input_directory),
+        "type,client,tx,amount\ndispute,1,1,\n",
+    )?;
+    fs::write(format!("{}/notes.txt", input_directory), "not transactions")?;
+    fs::write(
+        &extra_input_filename,
+        "type,client,tx,amount\nwithdrawal,2,3,1.0\n",
+    )?;
+
+    Command::cargo_bin("transaction-engine")?
+        .arg(&input_directory)
+        .arg(&extra_input_filename)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,0.0000,10.0000,false,10.0000"))
+        .stdout(predicate::str::contains("2,2.0000,0.0000,false,2.0000"));
+
+    fs::remove_dir_all(&input_directory)?;
+    fs::remove_file(&extra_input_filename)?;
+    Ok(())
+}
+
+#[test]
+fn test_headerless_input() -> Result<(), Box<dyn Error>> {
+    let input_file = "data/tests/headerless_transaction_batch_005.csv";
+
+    Command::cargo_bin("transaction-engine")?
+        .arg(input_file)
+        .arg("--no-headers")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,1.0000,0.0000,false,1.0000"));
+
+    Ok(())
+}
+
+#[test]
+fn test_custom_column_input() -> Result<(), Box<dyn Error>> {
+    let now_timestamp = Utc::now().format("%Y%m%d%H%M%S");
+    let input_filename = format!("data/tests/integ_test_partner_{}.csv", now_timestamp);
+
+    fs::write(
+        &input_filename,
+        "txn_id;client_id;type;amount\n1;7;deposit;12.5\n2;7;withdrawal;2.5\n",
+    )?;
+
+    Command::cargo_bin("transaction-engine")?
+        .arg(&input_filename)
+        .arg("--delimiter")
+        .arg(";")
+        .arg("--column-mapping")
+        .arg("client=client_id,tx=txn_id")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("7,10.0000,0.0000,false,10.0000"));
+
+    fs::remove_file(&input_filename)?;
+    Ok(())
+}
+
+#[test]
+fn test_json_input_and_output() -> Result<(), Box<dyn Error>> {
+    let input_data = "\
+        {\"type\":\"deposit\",\"client\":3,\"tx\":1,\"amount\":10.25}\n\
+        {\"type\":\"withdrawal\",\"client\":3,\"tx\":2,\"amount\":\"0.125\"}\n";
+
+    Command::cargo_bin("transaction-engine")?
+        .arg("-")
+        .arg("--input-format")
+        .arg("ndjson")
+        .arg("--output-format")
+        .arg("json")
+        .write_stdin(input_data)
+        .assert()
+        .success()
+        .stdout(
+            "[{\"client\":3,\"available\":\"10.1250\",\"held\":\"0.0000\",\"locked\":false,\"total\":\"10.1250\"}]\n",
+        );
+
+    Command::cargo_bin("transaction-engine")?
+        .arg("data/tests/transaction_batch_single_account.csv")
+        .arg("--output-format")
+        .arg("ndjson")
+        .assert()
+        .success()
+        .stdout(
+            "{\"client\":5,\"available\":\"435930.1231\",\"held\":\"0.0000\",\"locked\":false,\"total\":\"435930.1231\"}\n",
+        );
+
+    Ok(())
+}
+
+#[test]
+fn test_output_file_is_deterministic() -> Result<(), Box<dyn Error>> {
+    let input_file = "data/tests/transaction_batch_010.csv";
+    let now_timestamp = Utc::now().format("%Y%m%d%H%M%S");
+    let first_output_filename = format!("data/tests/integ_test_output_1_{}.csv", now_timestamp);
+    let second_output_filename = format!("data/tests/integ_test_output_2_{}.csv", now_timestamp);
+
+    for output_filename in [&first_output_filename, &second_output_filename] {
+        Command::cargo_bin("transaction-engine")?
+            .arg(input_file)
+            .arg("--output")
+            .arg(output_filename)
+            .assert()
+            .success()
+            .stdout("");
+    }
+
+    let first_output = fs::read(&first_output_filename)?;
+    assert_eq!(first_output, fs::read(&second_output_filename)?);
+    let clients: Vec<u16> = String::from_utf8(first_output)?
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap().parse().unwrap())
+        .collect();
+    assert!(clients.windows(2).all(|pair| pair[0] < pair[1]));
+
+    fs::remove_file(&first_output_filename)?;
+    fs::remove_file(&second_output_filename)?;
+    Ok(())
+}
+
+#[test]
+fn test_strict_and_quarantine_modes() -> Result<(), Box<dyn Error>> {
+    let (input_filename, quarantine_filename) =
+        (test_path("malformed.csv"), test_path("quarantine.csv"));
+
+    fs::write(
+        &input_filename,
+        "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,1,2,five\ndeposit,1,3,1.0\ndeposit,1\n",
+    )?;
+
+    Command::cargo_bin("transaction-engine")?
+        .arg(&input_filename)
+        .arg("--strict")
+        .assert()
+        .code(17)
+        .stdout("")
+        .stderr(predicate::str::starts_with(format!(
+            "Error: MalformedRecord - File [{}] line 3 column 4 - ",
+            input_filename
+        )));
+
+    let terminal_output = Command::cargo_bin("transaction-engine")?
+        .arg(&input_filename)
+        .arg("--strict")
+        .arg("--error-format")
+        .arg("json")
+        .assert()
+        .code(17)
+        .get_output()
+        .clone();
+    let error_report: serde_json::Value = serde_json::from_slice(&terminal_output.stderr)?;
+    assert_eq!(error_report["error"], "MalformedRecord");
+    assert_eq!(error_report["exit_code"], 17);
+    assert_eq!(error_report["file"], input_filename.as_str());
+    assert_eq!(error_report["line"], 3);
+    assert_eq!(error_report["column"], 4);
+
+    Command::cargo_bin("transaction-engine")?
+        .arg(&input_filename)
+        .arg("--quarantine-file-path")
+        .arg(&quarantine_filename)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,6.0000,0.0000,false,6.0000"));
+
+    let quarantine = fs::read_to_string(&quarantine_filename)?;
+    assert!(quarantine.starts_with(&format!(
+        "file,line,column,error,record\n{},3,4,",
+        input_filename
+    )));
+    assert!(quarantine.contains(",\"deposit,1,2,five\"\n"));
+    assert!(quarantine.ends_with(&format!(
+        "{},5,,\"Record has 2 fields, expected 4\",\"deposit,1\"\n",
+        input_filename
+    )));
+    assert_eq!(quarantine.lines().count(), 3);
+
+    fs::remove_file(&input_filename)?;
+    fs::remove_file(&quarantine_filename)?;
+    Ok(())
+}
+
+#[test]
+fn test_error_budget() -> Result<(), Box<dyn Error>> {
+    let now_timestamp = Utc::now().format("%Y%m%d%H%M%S");
+    let input_filename = format!("data/tests/integ_test_error_budget_{}.csv", now_timestamp);
+
+    fs::write(
+        &input_filename,
+        "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,9.0\ndeposit,1,x,1.0\ndeposit,1,3,1.0\n",
+    )?;
+
+    Command::cargo_bin("transaction-engine")?
+        .arg(&input_filename)
+        .arg("--max-errors")
+        .arg("1")
+        .assert()
+        .code(18)
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "Batch rejected - 2 errors in 3 records exceeds the maximum of 1 errors - Errors by category: InsufficientFunds 1, MalformedRecord 1",
+        ));
+
+    Command::cargo_bin("transaction-engine")?
+        .arg(&input_filename)
+        .arg("--max-error-rate")
+        .arg("0.25")
+        .assert()
+        .code(18)
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "exceeds the maximum error rate of 0.25",
+        ));
+
+    Command::cargo_bin("transaction-engine")?
+        .arg(&input_filename)
+        .arg("--max-errors")
+        .arg("2")
+        .arg("--max-error-rate")
+        .arg("0.5")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1,6.0000,0.0000,false,6.0000"));
+
+    fs::remove_file(&input_filename)?;
+    Ok(())
+}
+
+#[test]
+fn test_error_budget_requires_in_memory_state() -> Result<(), Box<dyn Error>> {
+    // A rejected batch would already have persisted the transactions applied before it failed
+    for (persistence_arg, budget_arg, budget_value) in [
+        ("--store-path", "--max-errors", "0"),
+        ("--journal-path", "--max-error-rate", "0.5"),
+    ] {
+        let persistence_filename = test_path(&format!("budget_{}", &persistence_arg[2..]));
+        Command::cargo_bin("transaction-engine")?
+            .arg("-")
+            .arg(persistence_arg)
+            .arg(&persistence_filename)
+            .arg(budget_arg)
+            .arg(budget_value)
+            .write_stdin("type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,20.0\n")
+            .assert()
+            .code(11)
+            .stdout("");
+        assert!(fs::metadata(&persistence_filename).is_err());
+    }
+
+    Ok(())
+}
+
 #[test]
 fn test_validation() -> Result<(), Box<dyn Error>> {
     let terminal_output = Command::cargo_bin("generate-test-data")?
@@ -40,27 +710,74 @@ fn test_validation() -> Result<(), Box<dyn Error>> {
     // For instance, tests/output.csv
     fs::write(&generated_input_filename, stdout_csv_str.as_ref())?;
 
+    // Input is streamed, no size limit applies by default
+    Command::cargo_bin("transaction-engine")?
+        .arg(&generated_input_filename)
+        .assert()
+        .success();
+
     // Test max file size validation
+    let max_file_size_mb = 2;
     let terminal_output = Command::cargo_bin("transaction-engine")?
         .arg(&generated_input_filename)
+        .arg("--max-file-size-mb")
+        .arg(max_file_size_mb.to_string())
         .assert()
-        .failure()
+        .code(11)
         .get_output()
         .clone();
     let error_response_msg = String::from_utf8_lossy(&terminal_output.stderr);
 
     println!("Error response message: {}", error_response_msg);
-    assert!(error_response_msg.contains("Error: InvalidData"));
+    assert!(error_response_msg.starts_with("Error: InvalidData - "));
     assert!(
         error_response_msg.contains(format!("Data file [{}]", generated_input_filename).as_str())
     );
     assert!(error_response_msg
-        .contains(format!("exceeds input limit of {} megabytes", MB_THRESHOLD).as_str()));
+        .contains(format!("exceeds input limit of {} megabytes", max_file_size_mb).as_str()));
+
+    // Limits too large to express in bytes do not overflow
+    Command::cargo_bin("transaction-engine")?
+        .arg(&generated_input_filename)
+        .arg("--max-file-size-mb")
+        .arg(u64::MAX.to_string())
+        .assert()
+        .success();
 
     fs::remove_file(&generated_input_filename)?;
     Ok(())
 }
 
+#[test]
+fn test_unreadable_input_reports_cause() -> Result<(), Box<dyn Error>> {
+    let missing_filename = "data/tests/integ_test_missing.csv";
+
+    Command::cargo_bin("transaction-engine")?
+        .arg(missing_filename)
+        .assert()
+        .code(10)
+        .stdout("")
+        .stderr(predicate::str::starts_with(format!(
+            "Error: FileAccess - Unable to open [\"{}\"] - ",
+            missing_filename
+        )));
+
+    let terminal_output = Command::cargo_bin("transaction-engine")?
+        .arg(missing_filename)
+        .arg("--error-format")
+        .arg("json")
+        .assert()
+        .code(10)
+        .get_output()
+        .clone();
+    let error_report: serde_json::Value = serde_json::from_slice(&terminal_output.stderr)?;
+    assert_eq!(error_report["error"], "FileAccess");
+    assert_eq!(error_report["exit_code"], 10);
+    assert_eq!(error_report["causes"].as_array().map(Vec::len), Some(1));
+
+    Ok(())
+}
+
 #[test]
 fn test_generator_tool_execution() -> Result<(), Box<dyn Error>> {
     Command::cargo_bin("generate-test-data")?